@@ -0,0 +1,172 @@
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AntiAliasing {
+    None,
+    /// Renders at twice the resolution on each axis and averages 2x2 samples per pixel
+    Ssaa4x,
+    /// Renders at four times the resolution on each axis and averages 4x4 samples per pixel
+    Ssaa16x,
+    /// Post process edge blur on the final image
+    Fxaa
+}
+impl AntiAliasing {
+    /// Render resolution multiplier on each axis
+    pub fn scale(self) -> u32 {
+        match self {
+            AntiAliasing::Ssaa4x => 2,
+            AntiAliasing::Ssaa16x => 4,
+            AntiAliasing::None | AntiAliasing::Fxaa => 1
+        }
+    }
+    pub fn next(self) -> Self {
+        match self {
+            AntiAliasing::None => AntiAliasing::Fxaa,
+            AntiAliasing::Fxaa => AntiAliasing::Ssaa4x,
+            AntiAliasing::Ssaa4x => AntiAliasing::Ssaa16x,
+            AntiAliasing::Ssaa16x => AntiAliasing::None
+        }
+    }
+}
+
+/// Box filters the `scale` times larger linear RGBA image `src` into `dst`
+pub fn downsample(
+    scale: usize,
+    width: usize, height: usize,
+    src: &[f32],
+    dst: &mut [f32]
+) {
+    let src_width = width * scale;
+    let weight = 1. / (scale * scale) as f32;
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0.;4];
+            for sy in 0..scale {
+                let row = ((y * scale + sy) * src_width + x * scale) * 4;
+                for sx in 0..scale {
+                    let i = row + sx * 4;
+                    sum[0] += src[i    ];
+                    sum[1] += src[i + 1];
+                    sum[2] += src[i + 2];
+                    sum[3] += src[i + 3];
+                }
+            }
+            let i = (y * width + x) * 4;
+            dst[i    ] = sum[0] * weight;
+            dst[i + 1] = sum[1] * weight;
+            dst[i + 2] = sum[2] * weight;
+            dst[i + 3] = sum[3] * weight;
+        }
+    }
+}
+
+const EDGE_THRESHOLD_MIN: f32 = 0.0312;
+const EDGE_THRESHOLD_MAX: f32 = 0.125;
+const SUBPIXEL_QUALITY: f32 = 0.75;
+const SEARCH_STEPS: [f32;12] = [1., 1., 1., 1., 1., 1.5, 2., 2., 2., 2., 4., 8.];
+
+/// FXAA 3.11 style anti-aliasing of the RGBA image `pixels`: finds the local edge orientation
+/// from luma, searches along the edge for its ends and blends each pixel across the edge
+pub fn fxaa(width: usize, height: usize, pixels: &mut [u8]) {
+    if width < 3 || height < 3 { return }
+    let src = pixels.to_vec();
+    let luma = src.chunks_exact(4)
+        .map(|p| (0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32) / 255.)
+        .collect::<Vec<_>>();
+    let at = |x: usize, y: usize| luma[y * width + x];
+    // Luma with linear filtering along one axis, clamped to the image
+    let sample = |x: f32, y: f32| {
+        let x = x.max(0.).min((width - 1) as f32);
+        let y = y.max(0.).min((height - 1) as f32);
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+        let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+        let top = at(x0, y0) + (at(x1, y0) - at(x0, y0)) * fx;
+        let bottom = at(x0, y1) + (at(x1, y1) - at(x0, y1)) * fx;
+        top + (bottom - top) * fy
+    };
+
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let m = at(x, y);
+            let n = at(x, y - 1);
+            let s = at(x, y + 1);
+            let w = at(x - 1, y);
+            let e = at(x + 1, y);
+            let max = m.max(n).max(s).max(w).max(e);
+            let min = m.min(n).min(s).min(w).min(e);
+            let range = max - min;
+            if range < EDGE_THRESHOLD_MIN.max(max * EDGE_THRESHOLD_MAX) { continue }
+
+            let nw = at(x - 1, y - 1);
+            let ne = at(x + 1, y - 1);
+            let sw = at(x - 1, y + 1);
+            let se = at(x + 1, y + 1);
+            let edge_horizontal =
+                (-2. * w + nw + sw).abs() + (-2. * m + n + s).abs() * 2. + (-2. * e + ne + se).abs();
+            let edge_vertical =
+                (-2. * n + nw + ne).abs() + (-2. * m + w + e).abs() * 2. + (-2. * s + sw + se).abs();
+            let horizontal = edge_horizontal >= edge_vertical;
+
+            // Side of the edge with the steepest gradient
+            let (luma1, luma2) = if horizontal { (n, s) } else { (w, e) };
+            let gradient1 = luma1 - m;
+            let gradient2 = luma2 - m;
+            let negative = gradient1.abs() >= gradient2.abs();
+            let gradient_scaled = 0.25 * gradient1.abs().max(gradient2.abs());
+            let (step, local_average) = if negative { (-1., 0.5 * (luma1 + m)) } else { (1., 0.5 * (luma2 + m)) };
+
+            // Walk both ways along the edge, half a pixel towards the chosen side
+            let (mut ux, mut uy) = (x as f32, y as f32);
+            if horizontal { uy += step * 0.5 } else { ux += step * 0.5 }
+            let (dx, dy) = if horizontal { (1., 0.) } else { (0., 1.) };
+            let (mut x1, mut y1) = (ux - dx, uy - dy);
+            let (mut x2, mut y2) = (ux + dx, uy + dy);
+            let mut end1 = sample(x1, y1) - local_average;
+            let mut end2 = sample(x2, y2) - local_average;
+            let mut reached1 = end1.abs() >= gradient_scaled;
+            let mut reached2 = end2.abs() >= gradient_scaled;
+            for quality in SEARCH_STEPS.iter().skip(1) {
+                if reached1 && reached2 { break }
+                if !reached1 {
+                    x1 -= dx * quality;
+                    y1 -= dy * quality;
+                    end1 = sample(x1, y1) - local_average;
+                    reached1 = end1.abs() >= gradient_scaled
+                }
+                if !reached2 {
+                    x2 += dx * quality;
+                    y2 += dy * quality;
+                    end2 = sample(x2, y2) - local_average;
+                    reached2 = end2.abs() >= gradient_scaled
+                }
+            }
+
+            let (distance1, distance2) = if horizontal {
+                (x as f32 - x1, x2 - x as f32)
+            } else {
+                (y as f32 - y1, y2 - y as f32)
+            };
+            let closest_end = if distance1 < distance2 { end1 } else { end2 };
+            let edge_offset = if (closest_end < 0.) != (m < local_average) {
+                0.5 - distance1.min(distance2) / (distance1 + distance2)
+            } else {
+                0.
+            };
+
+            let average = (2. * (n + s + w + e) + nw + ne + sw + se) / 12.;
+            let subpixel = ((average - m).abs() / range).min(1.);
+            let subpixel = (-2. * subpixel + 3.) * subpixel * subpixel;
+            let offset = edge_offset.max(subpixel * subpixel * SUBPIXEL_QUALITY);
+
+            let (nx, ny) = if horizontal {
+                (x, if negative { y - 1 } else { y + 1 })
+            } else {
+                (if negative { x - 1 } else { x + 1 }, y)
+            };
+            let i = (y * width + x) * 4;
+            let j = (ny * width + nx) * 4;
+            for c in 0..3 {
+                pixels[i + c] = (src[i + c] as f32 + (src[j + c] as f32 - src[i + c] as f32) * offset) as u8
+            }
+        }
+    }
+}
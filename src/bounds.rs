@@ -0,0 +1,73 @@
+use math::{Vec3, Transform};
+
+#[derive(Clone, Copy, Default)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3
+}
+impl Aabb {
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        let mut points = points.into_iter();
+        let Some(first) = points.next() else { return Self::default() };
+        points.fold(Self { min: first, max: first }, |aabb, p| Self {
+            min: Vec3::new(aabb.min.x.min(p.x), aabb.min.y.min(p.y), aabb.min.z.min(p.z)),
+            max: Vec3::new(aabb.max.x.max(p.x), aabb.max.y.max(p.y), aabb.max.z.max(p.z))
+        })
+    }
+    pub fn corners(&self) -> [Vec3;8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Vec3::new(a.x, a.y, a.z), Vec3::new(b.x, a.y, a.z),
+            Vec3::new(a.x, b.y, a.z), Vec3::new(b.x, b.y, a.z),
+            Vec3::new(a.x, a.y, b.z), Vec3::new(b.x, a.y, b.z),
+            Vec3::new(a.x, b.y, b.z), Vec3::new(b.x, b.y, b.z)
+        ]
+    }
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+    /// Box enclosing this one after `transform`
+    pub fn transformed(&self, transform: &Transform) -> Self {
+        Self::from_points(self.corners().map(|corner| transform.clone() * corner))
+    }
+    /// Slab test of the ray starting at `origin`, `inv_dir` holds the inverse of each direction component
+    #[inline(always)]
+    pub fn ray_hits(&self, origin: Vec3, inv_dir: Vec3) -> bool {
+        let (mut near, mut far) = (0f32, f32::INFINITY);
+        for (min, max, o, inv) in [
+            (self.min.x, self.max.x, origin.x, inv_dir.x),
+            (self.min.y, self.max.y, origin.y, inv_dir.y),
+            (self.min.z, self.max.z, origin.z, inv_dir.z)
+        ] {
+            let (t0, t1) = ((min - o) * inv, (max - o) * inv);
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1))
+        }
+        near <= far
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Sphere {
+    pub center: Vec3,
+    pub radius: f32
+}
+impl Sphere {
+    /// Sphere around the center of the points bounding box
+    pub fn from_points(points: impl IntoIterator<Item = Vec3> + Clone) -> Self {
+        let center = Aabb::from_points(points.clone()).center();
+        let radius = points.into_iter()
+            .map(|p| (p - center).dot(p - center))
+            .fold(0., f32::max)
+            .sqrt();
+        Self { center, radius }
+    }
+    /// Sphere enclosing this one after `transform`
+    pub fn transformed(&self, transform: &Transform) -> Self {
+        let scale = transform.scale.x.abs().max(transform.scale.y.abs()).max(transform.scale.z.abs());
+        Self {
+            center: transform.clone() * self.center,
+            radius: self.radius * scale
+        }
+    }
+}
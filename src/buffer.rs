@@ -0,0 +1,48 @@
+/// Contiguous row major 2D storage with `channels` interleaved values per texel
+#[derive(Clone)]
+pub struct Buffer<T = f32> {
+    pub width: usize,
+    pub height: usize,
+    pub channels: usize,
+    pub data: Vec<T>
+}
+impl<T> Buffer<T> {
+    pub fn from_fn(
+        width: usize, height: usize,
+        channels: usize,
+        mut f: impl FnMut(usize, usize, usize) -> T
+    ) -> Self {
+        let mut data = Vec::with_capacity(width * height * channels);
+        for y in 0..height {
+            for x in 0..width {
+                for channel in 0..channels {
+                    data.push(f(x, y, channel))
+                }
+            }
+        }
+        Self { width, height, channels, data }
+    }
+    #[inline(always)]
+    pub fn index(&self, x: usize, y: usize) -> usize {
+        (y * self.width + x) * self.channels
+    }
+    #[inline(always)]
+    pub fn texel(&self, x: usize, y: usize) -> &[T] {
+        let i = self.index(x, y);
+        &self.data[i..i + self.channels]
+    }
+    #[inline(always)]
+    pub fn texel_mut(&mut self, x: usize, y: usize) -> &mut [T] {
+        let i = self.index(x, y);
+        &mut self.data[i..i + self.channels]
+    }
+}
+impl<T: Clone> Buffer<T> {
+    pub fn new(width: usize, height: usize, channels: usize, value: T) -> Self {
+        Self {
+            width, height,
+            channels,
+            data: vec![value;width * height * channels]
+        }
+    }
+}
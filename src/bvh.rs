@@ -0,0 +1,122 @@
+use math::Vec3;
+
+use crate::bounds::Aabb;
+
+// Triangles below which a node is not split
+const LEAF_SIZE: usize = 4;
+// Deep enough for any median split tree of u32 indexed triangles
+const MAX_DEPTH: usize = 64;
+
+#[derive(Clone, Copy, Default)]
+pub struct BvhNode {
+    pub bounds: Aabb,
+    /// First of the two children of an inner node, the second follows it,
+    /// or first entry of `Bvh::triangles` for a leaf
+    pub start: u32,
+    /// Triangles of a leaf, 0 for inner nodes
+    pub count: u32
+}
+impl BvhNode {
+    #[inline(always)]
+    pub fn is_leaf(&self) -> bool {
+        self.count > 0
+    }
+}
+
+/// Bounding volume hierarchy over triangles, nodes are split at the median centroid along their longest axis
+pub struct Bvh {
+    /// Root first, empty without triangles
+    pub nodes: Vec<BvhNode>,
+    /// Triangle indices, each leaf references a contiguous range
+    pub triangles: Vec<u32>
+}
+impl Bvh {
+    pub fn new(triangles: &[[Vec3;3]]) -> Self {
+        let mut bvh = Self { nodes: Vec::new(), triangles: (0..triangles.len() as u32).collect() };
+        if triangles.is_empty() { return bvh }
+        let centers = triangles.iter().map(|&[a, b, c]| (a + b + c) * (1. / 3.)).collect::<Vec<_>>();
+        bvh.nodes.push(BvhNode::default());
+        bvh.split(0, 0, triangles.len(), triangles, &centers);
+        bvh
+    }
+    fn split(&mut self, node: usize, start: usize, end: usize, triangles: &[[Vec3;3]], centers: &[Vec3]) {
+        let range = &mut self.triangles[start..end];
+        let bounds = Aabb::from_points(range.iter().flat_map(|&t| triangles[t as usize]));
+        if end - start <= LEAF_SIZE {
+            self.nodes[node] = BvhNode { bounds, start: start as u32, count: (end - start) as u32 };
+            return
+        }
+        let spread = Aabb::from_points(range.iter().map(|&t| centers[t as usize]));
+        let size = spread.max - spread.min;
+        let axis = |p: Vec3| if size.x >= size.y && size.x >= size.z { p.x } else if size.y >= size.z { p.y } else { p.z };
+        let mid = (start + end) / 2;
+        range.select_nth_unstable_by(mid - start, |&a, &b| axis(centers[a as usize]).total_cmp(&axis(centers[b as usize])));
+
+        let first = self.nodes.len();
+        self.nodes.extend([BvhNode::default();2]);
+        self.nodes[node] = BvhNode { bounds, start: first as u32, count: 0 };
+        self.split(first, start, mid, triangles, centers);
+        self.split(first + 1, mid, end, triangles, centers)
+    }
+    /// Calls `hit` with every triangle of the leaves crossed by the ray, until it returns false
+    pub fn traverse(&self, origin: Vec3, dir: Vec3, mut hit: impl FnMut(usize) -> bool) {
+        if self.nodes.is_empty() { return }
+        let inv_dir = Vec3::new(1. / dir.x, 1. / dir.y, 1. / dir.z);
+        let mut stack = [0u32;MAX_DEPTH];
+        let mut len = 1;
+        while len > 0 {
+            len -= 1;
+            let node = &self.nodes[stack[len] as usize];
+            if !node.bounds.ray_hits(origin, inv_dir) { continue }
+            if node.is_leaf() {
+                for &triangle in self.triangles[node.start as usize..(node.start + node.count) as usize].iter() {
+                    if !hit(triangle as usize) { return }
+                }
+            } else {
+                stack[len] = node.start;
+                stack[len + 1] = node.start + 1;
+                len += 2
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn traversal_reaches_every_crossed_triangle() {
+        // Deterministic soup of small triangles in a 10 unit cube
+        let mut seed = 1u32;
+        let mut random = || {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (seed >> 8) as f32 / (1 << 24) as f32
+        };
+        let triangles = (0..500)
+            .map(|_| {
+                let p = Vec3::new(random(), random(), random()) * 10.;
+                [p, p + Vec3::new(random(), random(), random()), p + Vec3::new(random(), random(), random())]
+            })
+            .collect::<Vec<_>>();
+        let bvh = Bvh::new(&triangles);
+        for _ in 0..200 {
+            let origin = Vec3::new(random(), random(), random()) * 10.;
+            let dir = Vec3::new(random() - 0.5, random() - 0.5, random() - 0.5).normalized();
+            let inv_dir = Vec3::new(1. / dir.x, 1. / dir.y, 1. / dir.z);
+            let mut visited = vec![false;triangles.len()];
+            bvh.traverse(origin, dir, |t| { visited[t] = true; true });
+            for (t, triangle) in triangles.iter().enumerate() {
+                if Aabb::from_points(*triangle).ray_hits(origin, inv_dir) {
+                    assert!(visited[t], "triangle {t} missed")
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn empty() {
+        let bvh = Bvh::new(&[]);
+        bvh.traverse(Vec3::default(), Vec3::new(0., 0., 1.), |_| panic!("no triangle to visit"))
+    }
+}
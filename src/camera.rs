@@ -1,12 +1,19 @@
 use std::f32::consts::PI;
 
-use math::{Vec3, Mat4x4, Vec2, deg_to_rad};
+use math::{Vec3, Vec4, Mat4x4, Vec2, deg_to_rad};
+
+use crate::bounds::{Aabb, Sphere};
+
+pub const NEAR: f32 = 0.01;
+pub const FAR: f32 = 100.;
 
 pub struct Camera {
     pub translation: Vec3,
     pub rotation: Vec2,
     pub distance: f32,
     pub position: Vec3,
+    /// Unit view direction
+    pub forward: Vec3,
     pub mat: Mat4x4
 }
 impl Camera {
@@ -16,6 +23,7 @@ impl Camera {
             rotation: Vec2::new(deg_to_rad(-20.), 0.),
             distance: 10.,
             position: Default::default(),
+            forward: Default::default(),
             mat: Default::default()
         }
     }
@@ -25,9 +33,22 @@ impl Camera {
             .rotate_x(self.rotation.x)
             .rotate_y(self.rotation.y)
             + self.translation;
+        self.forward = (self.translation - self.position).normalized();
         let aspect = width as f32 / height as f32;
-        let proj = Mat4x4::perspective(aspect, aspect, 0.01, 100.);
+        let proj = Mat4x4::perspective(aspect, aspect, NEAR, FAR);
         let view = Mat4x4::look_at(self.position, self.translation);
         self.mat = proj * view;
     }
+    /// Conservative frustum test of world space bounds: the sphere is checked against the
+    /// near and far planes, then the box is culled when all its corners lie outside the
+    /// same side plane in clip space
+    pub fn is_visible(&self, aabb: &Aabb, sphere: &Sphere) -> bool {
+        let depth = (sphere.center - self.position).dot(self.forward);
+        if depth + sphere.radius < NEAR || depth - sphere.radius > FAR { return false }
+        let corners = aabb.corners().map(|corner| self.mat * corner.extend(1.));
+        let outside = |plane: fn(&Vec4) -> bool| corners.iter().all(plane);
+        !(outside(|c| c.x < -c.w) || outside(|c| c.x > c.w) ||
+          outside(|c| c.y < -c.w) || outside(|c| c.y > c.w) ||
+          outside(|c| c.w < NEAR))
+    }
 }
\ No newline at end of file
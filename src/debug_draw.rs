@@ -0,0 +1,110 @@
+use std::{sync::Mutex, f32::consts::TAU};
+use math::{Vec3, Transform};
+
+use crate::{bounds::Aabb, bvh::Bvh, object::Object, render::cross, mesh::NO_NEIGHBOR};
+
+const CIRCLE_SEGMENTS: usize = 24;
+
+/// World space segment, `color` is linear
+#[derive(Clone, Copy)]
+pub struct Line {
+    pub a: Vec3,
+    pub b: Vec3,
+    pub color: Vec3
+}
+
+/// Lines queued for the next frame, drawn depth tested over the scene and cleared once drawn
+pub struct DebugDraw {
+    lines: Mutex<Vec<Line>>
+}
+
+pub static DEBUG_DRAW: DebugDraw = DebugDraw { lines: Mutex::new(Vec::new()) };
+
+impl DebugDraw {
+    pub fn take(&self) -> Vec<Line> {
+        std::mem::take(&mut *self.lines.lock().unwrap())
+    }
+    pub fn line(&self, a: Vec3, b: Vec3, color: Vec3) {
+        self.lines.lock().unwrap().push(Line { a, b, color })
+    }
+    pub fn aabb(&self, aabb: &Aabb, color: Vec3) {
+        let c = aabb.corners();
+        let mut lines = self.lines.lock().unwrap();
+        for (a, b) in [(0, 1), (2, 3), (4, 5), (6, 7), (0, 2), (1, 3), (4, 6), (5, 7), (0, 4), (1, 5), (2, 6), (3, 7)] {
+            lines.push(Line { a: c[a], b: c[b], color })
+        }
+    }
+    /// Boxes of the nodes of `bvh` placed by `transform`, down to `depth` levels below the root,
+    /// from cyan at the root to red at the deepest level
+    pub fn bvh(&self, bvh: &Bvh, transform: &Transform, depth: usize) {
+        let mut stack = if bvh.nodes.is_empty() { Vec::new() } else { vec![(0, 0)] };
+        while let Some((node, level)) = stack.pop() {
+            let node = &bvh.nodes[node];
+            let t = level as f32 / depth.max(1) as f32;
+            self.aabb(&node.bounds.transformed(transform), Vec3::new(t, 1. - t, 1. - t));
+            if !node.is_leaf() && level < depth {
+                stack.push((node.start as usize, level + 1));
+                stack.push((node.start as usize + 1, level + 1))
+            }
+        }
+    }
+    /// Local x, y and z axes of `transform` in red, green and blue
+    pub fn axes(&self, transform: &Transform, size: f32) {
+        let origin = transform.translation;
+        self.line(origin, origin + transform.rotation * Vec3::new(size, 0., 0.), Vec3::new(1., 0., 0.));
+        self.line(origin, origin + transform.rotation * Vec3::new(0., size, 0.), Vec3::new(0., 1., 0.));
+        self.line(origin, origin + transform.rotation * Vec3::new(0., 0., size), Vec3::new(0., 0., 1.))
+    }
+    pub fn arrow(&self, from: Vec3, to: Vec3, color: Vec3) {
+        let length = (to - from).dot(to - from).sqrt();
+        if length <= 0. { return }
+        let dir = (to - from) * (1. / length);
+        let (u, v) = basis(dir);
+        let head = length * 0.15;
+        let base = to - dir * head;
+        self.line(from, to, color);
+        for side in [u, u * -1., v, v * -1.] {
+            self.line(to, base + side * (head * 0.5), color)
+        }
+    }
+    /// Circles around the three axes
+    pub fn sphere(&self, center: Vec3, radius: f32, color: Vec3) {
+        let point = |axis: usize, angle: f32| {
+            let (s, c) = (angle.sin() * radius, angle.cos() * radius);
+            center + match axis {
+                0 => Vec3::new(0., c, s),
+                1 => Vec3::new(c, 0., s),
+                _ => Vec3::new(c, s, 0.)
+            }
+        };
+        let mut lines = self.lines.lock().unwrap();
+        for axis in 0..3 {
+            for i in 0..CIRCLE_SEGMENTS {
+                let a = TAU * i as f32 / CIRCLE_SEGMENTS as f32;
+                let b = TAU * (i + 1) as f32 / CIRCLE_SEGMENTS as f32;
+                lines.push(Line { a: point(axis, a), b: point(axis, b), color })
+            }
+        }
+    }
+    /// Edges of every triangle of `object`, edges without a single neighbor
+    /// (holes and non manifold edges) are drawn with `open_color`
+    pub fn wireframe(&self, object: &Object, color: Vec3, open_color: Vec3) {
+        let transform = object.transform.lock().unwrap().clone();
+        let positions = object.model.mesh.vertices.iter().map(|vertex| transform.clone() * vertex.position).collect::<Vec<_>>();
+        let mut lines = self.lines.lock().unwrap();
+        for (t, triangle) in object.model.mesh.indices.iter().enumerate() {
+            let p = triangle.map(|i| positions[i as usize]);
+            for e in 0..3 {
+                let open = object.model.mesh.adjacency.as_ref().map_or(false, |adjacency| adjacency[t][e] == NO_NEIGHBOR);
+                lines.push(Line { a: p[e], b: p[(e + 1) % 3], color: if open { open_color } else { color } })
+            }
+        }
+    }
+}
+
+/// Two unit vectors perpendicular to `dir` and to each other
+fn basis(dir: Vec3) -> (Vec3, Vec3) {
+    let up = if dir.y.abs() < 0.99 { Vec3::new(0., 1., 0.) } else { Vec3::new(1., 0., 0.) };
+    let u = cross(dir, up).normalized();
+    (u, cross(dir, u))
+}
@@ -1,8 +1,23 @@
+use std::sync::{Mutex, atomic::Ordering};
 use winit::{event_loop::EventLoop, window::{WindowBuilder, Window}, dpi::PhysicalSize};
 use pixels::{Pixels, SurfaceTexture};
-use math::Quaternion;
+use math::{Quaternion, Vec3, Transform};
 
-use crate::{object::Object, camera::Camera, dir_light::DirectionalLight, render::{draw, clear}, text::{render_text, Log}};
+use crate::{
+    object::Object, camera::Camera, dir_light::DirectionalLight,
+    render::{ScreenTriangle, ScreenLine, TransformedVertex, Tile, ViewMode, create_tiles, project, project_lines, sort_transparent, bin, draw, resolve},
+    text::{render_text, Log},
+    antialiasing::{AntiAliasing, downsample, fxaa},
+    tonemap::ToneMapper,
+    debug_draw::DEBUG_DRAW,
+    gimap::GENERATION,
+    hierarchy::Hierarchy,
+    scene::Scene,
+    error::{Error, Result}
+};
+
+// Levels of the object BVHs drawn by `show_bvh`
+const BVH_DEPTH: usize = 6;
 
 pub struct Engine {
     pub buff_w4: i32,
@@ -10,12 +25,38 @@ pub struct Engine {
     pub width: u32,
     pub height: u32,
     pub pixels: Pixels,
-    pub zbuffer: Vec<f32>,
+    pub tiles: Vec<Tile>,
+    /// Resolution multiplier the tiles were created for
+    pub tiles_scale: u32,
+    /// Linear HDR image resolved from the tiles, at the render resolution
+    pub hdr: Vec<f32>,
+    /// `hdr` downsampled to the window resolution when supersampling
+    pub frame: Vec<f32>,
+    pub anti_aliasing: AntiAliasing,
+    pub tone_mapper: ToneMapper,
+    pub view_mode: ViewMode,
+    pub wireframe: bool,
+    /// Draws the object bounds and axes and the light direction
+    pub show_helpers: bool,
+    /// Draws the top levels of the BVH of every object
+    pub show_bvh: bool,
+    /// Spins the first object if any, the lightmaps fall back to direct lighting while anything moves
+    pub animate: bool,
+    pub vertices: Vec<TransformedVertex>,
+    pub triangles: Vec<ScreenTriangle>,
+    pub lines: Vec<ScreenLine>,
+    pub threads: usize,
     
     pub window: Window,
     pub objects: &'static [Object],
+    /// Places the objects that have a node, the others keep their own transform
+    pub hierarchy: Hierarchy,
+    /// Node of `hierarchy` moved by `move_node`
+    pub moved: Option<usize>,
     pub camera: Camera,
     pub dir_light: &'static DirectionalLight,
+    /// Light direction and object transforms of the current lightmap `GENERATION`
+    pub lighting: Vec<f32>,
 
     pub logs: Vec<Log>
 }
@@ -23,9 +64,9 @@ pub struct Engine {
 impl Engine {
     pub fn new(
         event_loop: &EventLoop<()>,
-        objects: &'static [Object],
+        scene: Scene,
         logs: Vec<Log>
-    ) -> Self {
+    ) -> Result<Self> {
         let window = WindowBuilder::new()
             .with_title("Global illumination")
             .with_resizable(false)
@@ -33,56 +74,182 @@ impl Engine {
                 width: 800,
                 height: 600
             })
-            .build(&event_loop).unwrap();
+            .build(&event_loop)
+            .map_err(|error| Error::platform(format!("creating the window: {error}")))?;
         let width = window.inner_size().width;
         let height = window.inner_size().height;
-        let pixels = Pixels::new(width, height, SurfaceTexture::new(width, height, &window)).unwrap();
-        Self {
+        let pixels = Pixels::new(width, height, SurfaceTexture::new(width, height, &window))
+            .map_err(|error| Error::platform(format!("creating the pixel buffer: {error}")))?;
+        let objects = scene.objects.leak();
+        let dir_light = Box::leak(Box::new(scene.dir_light));
+        Ok(Self {
             buff_w4: window.inner_size().width as i32 * 4,
 
             width: window.inner_size().width,
             height: window.inner_size().height,
             pixels,
-            zbuffer: vec![f32::MAX;(width * height)as usize],
+            tiles: Vec::new(),
+            tiles_scale: 0,
+            hdr: Vec::new(),
+            frame: vec![0.;(width * height * 4)as usize],
+            anti_aliasing: AntiAliasing::None,
+            tone_mapper: ToneMapper::default(),
+            view_mode: ViewMode::Shaded,
+            wireframe: false,
+            show_helpers: false,
+            show_bvh: false,
+            animate: false,
+            vertices: Vec::new(),
+            triangles: Vec::new(),
+            lines: Vec::new(),
+            threads: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
             
             window,
             objects,
-            camera: Camera::new(),
-            dir_light: Box::leak(Box::new(DirectionalLight::default())),
+            hierarchy: scene.hierarchy,
+            moved: scene.moved,
+            camera: scene.camera,
+            dir_light,
+            lighting: lighting(objects, dir_light),
 
             logs
-        }
+        })
     }
     pub fn rotate_object(&mut self) {
-        let object = &self.objects.first().unwrap();
-        let mut transform = object.transform.lock().unwrap();
-        transform.rotation = transform.rotation * Quaternion::from_angle_y(0.001);
+        let rotation = Quaternion::from_angle_y(0.001);
+        // The world transform of objects in the hierarchy is overwritten each frame, so their node is rotated
+        if let Some(node) = self.hierarchy.find_object(0) {
+            let local = &mut self.hierarchy.nodes[node].local;
+            local.rotation = local.rotation * rotation
+        } else if let Some(object) = self.objects.first() {
+            let mut transform = object.transform.lock().unwrap();
+            transform.rotation = transform.rotation * rotation
+        }
+    }
+    /// Moves the `moved` node along with everything under it
+    pub fn move_node(&mut self, offset: Vec3) {
+        if let Some(node) = self.moved {
+            let local = &mut self.hierarchy.nodes[node].local;
+            local.translation = local.translation + offset
+        }
     }
     pub fn update(&mut self) {
-        self.rotate_object();
+        if self.animate { self.rotate_object() }
+        self.hierarchy.update(self.objects);
+        let lighting = lighting(self.objects, self.dir_light);
+        if lighting != self.lighting {
+            GENERATION.fetch_add(1, Ordering::Release);
+            self.lighting = lighting
+        }
         
-        let pixels = self.pixels.frame_mut();
-        clear(pixels);
+        let scale = self.anti_aliasing.scale();
+        let width = (self.width * scale) as i32;
+        let height = (self.height * scale) as i32;
+        if self.tiles_scale != scale {
+            self.tiles = create_tiles(width, height);
+            self.hdr = vec![0.;(width * height * 4)as usize];
+            self.tiles_scale = scale
+        }
         self.camera.update(self.width, self.height);
         
-        self.zbuffer.fill(f32::MAX);
-        
-        for object in self.objects.iter() {
-            draw(
-                self.width as i32, self.height as i32,
-                pixels,
-                &mut self.zbuffer,
+        self.triangles.clear();
+        for (id, object) in self.objects.iter().enumerate() {
+            let transform = object.transform.lock().unwrap().clone();
+            if !self.camera.is_visible(&object.model.bounds.transformed(&transform), &object.model.bounding_sphere.transformed(&transform)) {
+                continue
+            }
+            project(
+                width, height,
+                id,
                 object,
-                &self.camera
+                &transform,
+                &self.camera,
+                &mut self.vertices,
+                &mut self.triangles
             )
         }
+        sort_transparent(&mut self.triangles);
+
+        for object in self.objects.iter() {
+            if self.wireframe {
+                DEBUG_DRAW.wireframe(object, Vec3::new(0., 1., 0.), Vec3::new(1., 0., 0.))
+            }
+            if self.show_helpers {
+                let transform = object.transform.lock().unwrap().clone();
+                DEBUG_DRAW.aabb(&object.model.bounds.transformed(&transform), Vec3::new(1., 1., 0.));
+                let sphere = object.model.bounding_sphere.transformed(&transform);
+                DEBUG_DRAW.sphere(sphere.center, sphere.radius, Vec3::new(0., 1., 1.));
+                DEBUG_DRAW.axes(&transform, 1.)
+            }
+            if self.show_bvh {
+                DEBUG_DRAW.bvh(&object.model.bvh, &object.transform.lock().unwrap().clone(), BVH_DEPTH)
+            }
+        }
+        if self.show_helpers {
+            let light = self.dir_light.direction.normalized();
+            DEBUG_DRAW.sphere(light * 5., 0.25, Vec3::new(1., 0.5, 0.));
+            DEBUG_DRAW.arrow(light * 5., Vec3::default(), Vec3::new(1., 0.5, 0.))
+        }
+        self.lines.clear();
+        project_lines(width, height, &DEBUG_DRAW.take(), &self.camera, &mut self.lines);
+
+        bin(width, height, &self.triangles, &self.lines, &mut self.tiles);
+
+        let triangles = &self.triangles;
+        let lines = &self.lines;
+        let objects = self.objects;
+        let camera = &self.camera;
+        let dir_light = self.dir_light;
+        let view_mode = self.view_mode;
+        let tiles = Mutex::new(self.tiles.iter_mut());
+        std::thread::scope(|s| {
+            for _ in 0..self.threads {
+                s.spawn(|| loop {
+                    let tile = tiles.lock().unwrap().next();
+                    match tile {
+                        Some(tile) => draw(tile, triangles, lines, objects, camera, dir_light, view_mode),
+                        None => break
+                    }
+                });
+            }
+        });
+
+        resolve(width, &mut self.hdr, &self.tiles);
+        let hdr = if scale == 1 {
+            &self.hdr
+        } else {
+            downsample(scale as usize, self.width as usize, self.height as usize, &self.hdr, &mut self.frame);
+            &self.frame
+        };
+        let pixels = self.pixels.frame_mut();
+        let tone_mapper = if self.view_mode == ViewMode::Shaded { &self.tone_mapper } else { &ToneMapper::RAW };
+        tone_mapper.encode(self.width as usize, hdr, pixels);
+        if self.anti_aliasing == AntiAliasing::Fxaa {
+            fxaa(self.width as usize, self.height as usize, pixels)
+        }
 
+        let modes = format!(
+            "AA {:?}, tone mapping {:?}{}, exposure {:+}, view {:?}",
+            self.anti_aliasing, self.tone_mapper.operator, if self.tone_mapper.dither { " dithered" } else { "" },
+            self.tone_mapper.exposure, self.view_mode
+        );
         render_text(
             self.width as usize,
             pixels,
-            &self.logs.iter().map(|log|log.get()).collect::<Vec<_>>().join("\n")
+            &[modes].into_iter().chain(self.logs.iter().map(|log|log.get())).collect::<Vec<_>>().join("\n")
         );
 
         self.pixels.render().unwrap()
     }
-}
\ No newline at end of file
+}
+
+/// Everything the lightmaps depend on that can change at runtime
+fn lighting(objects: &[Object], dir_light: &DirectionalLight) -> Vec<f32> {
+    let light = dir_light.direction;
+    let mut res = vec![light.x, light.y, light.z];
+    for object in objects.iter() {
+        let Transform { translation: t, rotation: r, scale: s } = object.transform.lock().unwrap().clone();
+        res.extend([t.x, t.y, t.z, r.x, r.y, r.z, r.w, s.x, s.y, s.z])
+    }
+    res
+}
@@ -0,0 +1,94 @@
+use std::{fmt, path::PathBuf};
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    Io(std::io::Error),
+    /// The file could not be parsed or decoded
+    Decode(String),
+    /// Valid data using a feature this renderer does not implement
+    Unsupported(String),
+    /// Data that breaks the format rules, like out of range indices
+    Validation(String),
+    /// Window or graphics surface creation failed
+    Platform(String)
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub path: Option<PathBuf>,
+    /// Element of the file the error was found in, like `mesh 2 primitive 0`
+    pub location: Option<String>
+}
+impl Error {
+    pub fn new(kind: ErrorKind) -> Self {
+        Self { kind, path: None, location: None }
+    }
+    pub fn decode(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Decode(message.into()))
+    }
+    pub fn unsupported(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Unsupported(message.into()))
+    }
+    pub fn validation(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Validation(message.into()))
+    }
+    pub fn platform(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Platform(message.into()))
+    }
+    pub fn in_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+    pub fn at(mut self, location: impl Into<String>) -> Self {
+        self.location = Some(location.into());
+        self
+    }
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(path) = &self.path { write!(f, "{}: ", path.display())? }
+        if let Some(location) = &self.location { write!(f, "{location}: ")? }
+        match &self.kind {
+            ErrorKind::Io(error) => write!(f, "{error}"),
+            ErrorKind::Decode(message) => write!(f, "decode error: {message}"),
+            ErrorKind::Unsupported(message) => write!(f, "unsupported: {message}"),
+            ErrorKind::Validation(message) => write!(f, "invalid data: {message}"),
+            ErrorKind::Platform(message) => write!(f, "{message}")
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            ErrorKind::Io(error) => Some(error),
+            _ => None
+        }
+    }
+}
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Self::new(ErrorKind::Io(error))
+    }
+}
+impl From<image::ImageError> for Error {
+    fn from(error: image::ImageError) -> Self {
+        match error {
+            image::ImageError::IoError(error) => error.into(),
+            image::ImageError::Unsupported(error) => Self::unsupported(error.to_string()),
+            error => Self::decode(error.to_string())
+        }
+    }
+}
+impl From<gltf::Error> for Error {
+    fn from(error: gltf::Error) -> Self {
+        match error {
+            gltf::Error::Io(error) => error.into(),
+            gltf::Error::Validation(_) => Self::validation(error.to_string()),
+            gltf::Error::UnsupportedImageEncoding | gltf::Error::UnsupportedScheme => Self::unsupported(error.to_string()),
+            error => Self::decode(error.to_string())
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
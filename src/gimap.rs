@@ -1,7 +1,16 @@
-use std::{sync::{Mutex, atomic::AtomicU32}, mem::transmute};
-use math::Vec3;
+use std::{sync::{Mutex, atomic::{AtomicU32, Ordering}}, mem::transmute};
+use math::{Vec3, Vec2, Quaternion, Transform};
 
-use crate::{object::{Object, Vertex}, dir_light::DirectionalLight, texture::Texture};
+use crate::{object::Object, mesh::Mesh, buffer::Buffer, dir_light::DirectionalLight, raster::raster_triangle, render::cross, material::AlphaMode};
+
+// Offset of shadow ray origins along the surface normal, avoids self intersections
+const SHADOW_BIAS: f32 = 1e-3;
+
+/// Lightmap texels per base color texel
+pub const GI_TEXTURE_SCALE: f32 = 1. / 10.;
+
+/// Bumped whenever an object moves or the light turns, lightmaps baked for an older generation are stale
+pub static GENERATION: AtomicU32 = AtomicU32::new(0);
 
 #[derive(Default, Clone, Copy)]
 pub struct GITextureVertex {
@@ -10,137 +19,193 @@ pub struct GITextureVertex {
 }
 
 pub struct GIMap {
-    pub obj_id: u32,
     pub width: usize,
     pub height: usize,
-    pub values: Vec<Vec<AtomicU32>>,
-    pub vertices: Mutex<Vec<Vec<GITextureVertex>>>
+    pub values: Buffer<AtomicU32>,
+    /// `GENERATION` of the last complete lighting pass in `values` plus one, 0 before the first
+    pub baked: AtomicU32,
+    pub vertices: Mutex<Buffer<GITextureVertex>>
 }
 impl GIMap {
     pub fn new(
-        obj_id: u32,
-        texture: &Texture,
-        triangles: &[[Vertex;3]],
+        texture_size: Vec2,
+        mesh: &Mesh,
         gi_texture_scale: f32
     ) -> Self {
-        let width = texture.size.x * gi_texture_scale;
-        let height = texture.size.y * gi_texture_scale;
+        // At least a texel, small textures would leave nothing to bake
+        let width = (texture_size.x * gi_texture_scale).floor().max(1.);
+        let height = (texture_size.y * gi_texture_scale).floor().max(1.);
         
-        let mut values = Vec::with_capacity(height as usize);
-        for _ in 0..height as usize {
-            let mut row = Vec::with_capacity(width as usize);
-            for _ in 0..width as usize {
-                row.push(AtomicU32::new(unsafe{ transmute(1f32) }))
-            }
-            values.push(row)
-        }
+        let values = Buffer::from_fn(width as usize, height as usize, 1, |_, _, _|
+            AtomicU32::new(unsafe{ transmute(1f32) })
+        );
 
-        let mut vertices = vec![vec![Default::default(); width as usize]; height as usize];
-        for (triangle_id, [a, b, c]) in triangles.iter().enumerate() {
+        let mut vertices = Buffer::new(width as usize, height as usize, 1, GITextureVertex::default());
+        for triangle_id in 0..mesh.indices.len() {
+            let [a, b, c] = mesh.triangle(triangle_id);
             raster_triangle(
-                width as i32, height as i32,
-                &mut vertices,
-                triangle_id as u32,
-                (a.uv.x * width)as i32, (a.uv.y * height)as i32,
-                (b.uv.x * width)as i32, (b.uv.y * height)as i32,
-                (c.uv.x * width)as i32, (c.uv.y * height)as i32,
-                Vec3::new(a.position.x, b.position.x, c.position.x),
-                Vec3::new(a.position.y, b.position.y, c.position.y),
-                Vec3::new(a.position.z, b.position.z, c.position.z)
+                0, 0, width as i32, height as i32,
+                Vec2::new(a.uv.x * width, a.uv.y * height),
+                Vec2::new(b.uv.x * width, b.uv.y * height),
+                Vec2::new(c.uv.x * width, c.uv.y * height),
+                |x, y, baryc| vertices.texel_mut(x as usize, y as usize)[0] = GITextureVertex {
+                    position: a.position * baryc.x + b.position * baryc.y + c.position * baryc.z,
+                    triangle_id: triangle_id as u32
+                }
             )
         }
         
         Self {
-            obj_id,
             width: width as usize,
             height: height as usize,
             values,
+            baked: AtomicU32::new(0),
             vertices: vertices.into()
         }
     }
+    /// Whether `values` hold a complete pass with the current transforms and light
+    #[inline(always)]
+    pub fn is_baked(&self) -> bool {
+        self.baked.load(Ordering::Relaxed) == GENERATION.load(Ordering::Relaxed).wrapping_add(1)
+    }
     #[inline(always)]
     pub fn get_value(&self, x: f32, y: f32) -> f32 {
-        unsafe { transmute(self.values[y as usize][x as usize].load(std::sync::atomic::Ordering::Relaxed)) }
+        unsafe { transmute(self.values.texel(x as usize, y as usize)[0].load(std::sync::atomic::Ordering::Relaxed)) }
     }
     #[inline(always)]
     pub fn set_value(&self, x: usize, y: usize, v: f32) {
-        self.values[y][x].store(unsafe { transmute(v) }, std::sync::atomic::Ordering::Relaxed)
+        self.values.texel(x, y)[0].store(unsafe { transmute(v) }, std::sync::atomic::Ordering::Relaxed)
     }
+    /// Lighting pass of the map of `objects[index]`, with the objects placed as they were when `occluders` was built
     pub fn update(
         &self,
+        index: usize,
         objects: &[Object],
+        occluders: &Occluders,
         dir_light: &DirectionalLight
     ) {
-        let s_object = &objects[self.obj_id as usize];
-        let transform = s_object.transform.lock().unwrap().clone();
+        let s_object = &objects[index];
+        let transform = occluders.transforms[index].clone();
         let vertices = self.vertices.lock().unwrap();
+        let light = dir_light.direction.normalized();
         for y in 0..self.height {
             for x in 0..self.width {
-                let vertex = vertices[y][x];
-                let triangle = &s_object.triangles[vertex.triangle_id as usize][0];
+                let vertex = vertices.texel(x, y)[0];
+                let triangle = &s_object.model.mesh.vertices[s_object.model.mesh.indices[vertex.triangle_id as usize][0] as usize];
                 let object_normal = (transform.rotation * triangle.normal).normalized();
-                let intensity = object_normal.dot(dir_light.direction);
+                let uv = Vec2::new((x as f32 + 0.5) / self.width as f32, (y as f32 + 0.5) / self.height as f32);
+                let surface = s_object.material(vertex.triangle_id as usize).surface(uv, Vec2::default(), Vec2::default());
+                let mut intensity = surface.diffuse_weight(object_normal.dot(light));
+                if intensity > 0. {
+                    let origin = transform * vertex.position + object_normal * SHADOW_BIAS;
+                    intensity *= transmittance(objects, &occluders.transforms, origin, light)
+                }
                 self.set_value(x, y, intensity)
             }
         }
+        self.baked.store(occluders.generation.wrapping_add(1), Ordering::Relaxed)
+    }
+}
+/// Object transforms the shadow rays are traced with, kept by the GI thread across passes until something moves
+pub struct Occluders {
+    /// `GENERATION` the transforms were read in
+    pub generation: u32,
+    /// In the order of the objects
+    pub transforms: Vec<Transform>
+}
+impl Occluders {
+    pub fn new(objects: &[Object]) -> Self {
+        // Read before the transforms, so a move while building leaves the passes stale
+        let generation = GENERATION.load(Ordering::Acquire);
+        Self { generation, transforms: objects.iter().map(|object| object.transform.lock().unwrap().clone()).collect() }
     }
 }
-#[inline(always)]
-fn raster_triangle(
-    width: i32, height: i32,
-    vertices: &mut Vec<Vec<GITextureVertex>>,
-    triangle_id: u32,
-    ax: i32, ay: i32,
-    bx: i32, by: i32,
-    cx: i32, cy: i32,
-    px: Vec3, py: Vec3, pz: Vec3
-) {
-    let max_width = width - 1;
-    let max_height = height - 1;
-    
-    let minx = max_width.min(ax).max(0).min(bx).max(0).min(cx).max(0);
-    let mut miny = max_height.min(ay).max(0).min(by).max(0).min(cy).max(0);
-    let maxx = 0.max(ax).min(max_width).max(bx).min(max_width).max(cx).min(max_width);
-    let maxy = 0.max(ay).min(max_height).max(by).min(max_height).max(cy).min(max_height);
-
-    let l1x = cx as f32 - ax as f32;
-    let l1y = bx as f32 - ax as f32;
-    let mut l1z;
-    let l2x = cy as f32 - ay as f32;
-    let l2y = by as f32 - ay as f32;
-    let mut l2z;
-    
-    let mut ux; let mut uy;
-    let uz = (l1x * l2y) - (l1y * l2x);
-    if uz.abs() < 1. { return }
-
-    let mut x;
-    let mut baryc = Vec3::default();
-    
-    while miny <= maxy {
-        l2z = ay as f32 - miny as f32;
-        x = minx;
-        while x <= maxx {
-            l1z = ax as f32 - x as f32;
-            ux = (l1y * l2z) - (l1z * l2y);
-            uy = (l1z * l2x) - (l1x * l2z);
 
-            baryc.x = 1.-(ux+uy)/uz;
-            if baryc.x < 0. { x += 1; continue }
+/// Ray from `origin` along `dir` in the space of the model placed by `transform`, none for a zero scale.
+/// The direction keeps the scale so distances along the ray are the same in both spaces
+fn to_object_space(transform: &Transform, origin: Vec3, dir: Vec3) -> Option<(Vec3, Vec3)> {
+    let Transform { translation: t, rotation: r, scale: s } = transform.clone();
+    if s.x == 0. || s.y == 0. || s.z == 0. { return None }
+    let norm = r.x * r.x + r.y * r.y + r.z * r.z + r.w * r.w;
+    let inverse = Quaternion { x: -r.x / norm, y: -r.y / norm, z: -r.z / norm, w: r.w / norm };
+    let local = |v: Vec3| {
+        let v = inverse * v;
+        Vec3::new(v.x / s.x, v.y / s.y, v.z / s.z)
+    };
+    Some((local(origin - t), local(dir)))
+}
 
-            baryc.y = uy/uz;
-            if baryc.y < 0. { x += 1; continue }
+/// Light reaching `origin` along `dir` through the scene triangles, cut out texels of masked
+/// materials let the light through and blended ones attenuate it by their opacity
+fn transmittance(
+    objects: &[Object],
+    transforms: &[Transform],
+    origin: Vec3,
+    dir: Vec3
+) -> f32 {
+    let mut res = 1.;
+    for (object, transform) in objects.iter().zip(transforms) {
+        let Some((origin, dir)) = to_object_space(transform, origin, dir) else { continue };
+        let mesh = &object.model.mesh;
+        object.model.bvh.traverse(origin, dir, |triangle_id| {
+            let [ta, tb, tc] = mesh.triangle(triangle_id);
+            let Some((u, v)) = intersect(origin, dir, ta.position, tb.position, tc.position) else { return true };
+            let material = object.material(triangle_id);
+            if material.alpha_mode == AlphaMode::Opaque {
+                res = 0.;
+                return false
+            }
+            let uv = Vec2::new(
+                ta.uv.x * (1. - u - v) + tb.uv.x * u + tc.uv.x * v,
+                ta.uv.y * (1. - u - v) + tb.uv.y * u + tc.uv.y * v
+            );
+            res *= material.transmittance(material.surface(uv, Vec2::default(), Vec2::default()).alpha);
+            res > 0.
+        });
+        if res <= 0. { return 0. }
+    }
+    res
+}
+/// Möller-Trumbore ray triangle intersection, returns the barycentric weights of `b` and `c`
+#[inline(always)]
+fn intersect(origin: Vec3, dir: Vec3, a: Vec3, b: Vec3, c: Vec3) -> Option<(f32, f32)> {
+    let e1 = b - a;
+    let e2 = c - a;
+    let p = cross(dir, e2);
+    let det = e1.dot(p);
+    if det.abs() < 1e-8 { return None }
+    let inv_det = 1. / det;
+    let s = origin - a;
+    let u = s.dot(p) * inv_det;
+    if !(0. ..=1.).contains(&u) { return None }
+    let q = cross(s, e1);
+    let v = dir.dot(q) * inv_det;
+    if v < 0. || u + v > 1. { return None }
+    if e2.dot(q) * inv_det <= 0. { return None }
+    Some((u, v))
+}
 
-            baryc.z = ux/uz;
-            if baryc.z < 0. { x += 1; continue }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            vertices[miny as usize][x as usize] = GITextureVertex {
-                position: Vec3::new(px.dot(baryc), py.dot(baryc), pz.dot(baryc)),
-                triangle_id
-            };
-            
-            x += 1;
+    #[test]
+    fn object_space_rays_hit_the_same_points() {
+        let transform = Transform {
+            translation: Vec3::new(1., -2., 3.),
+            rotation: Quaternion::from_angle_y(0.7),
+            scale: Vec3::new(2., 0.5, 3.)
+        };
+        let origin = Vec3::new(0.3, 4., -1.);
+        let dir = Vec3::new(0.2, -1., 0.4).normalized();
+        let (local_origin, local_dir) = to_object_space(&transform, origin, dir).unwrap();
+        for t in [0., 1., 7.5] {
+            let world = origin + dir * t;
+            let back = transform.clone() * (local_origin + local_dir * t);
+            for (a, b) in [(world.x, back.x), (world.y, back.y), (world.z, back.z)] {
+                assert!((a - b).abs() < 1e-4, "{a} != {b} at {t}")
+            }
         }
-        miny += 1;
+        assert!(to_object_space(&Transform { scale: Vec3::new(1., 0., 1.), ..transform }, origin, dir).is_none())
     }
-}
\ No newline at end of file
+}
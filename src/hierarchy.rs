@@ -0,0 +1,107 @@
+use math::Transform;
+
+use crate::object::{Object, compose, shears};
+
+pub struct Node {
+    pub name: String,
+    /// Relative to the parent node
+    pub local: Transform,
+    pub parent: Option<usize>,
+    /// Index of the object placed by this node, nodes without one only group their children
+    pub object: Option<usize>,
+    /// Derived from the local transforms by `Hierarchy::update`
+    pub world: Transform
+}
+
+/// Parent child tree of transforms, the world transform of every node is derived each frame
+/// and written to its object, so moving a node moves all the objects under it
+#[derive(Default)]
+pub struct Hierarchy {
+    pub nodes: Vec<Node>
+}
+impl Hierarchy {
+    pub fn add(
+        &mut self,
+        name: impl Into<String>,
+        local: Transform,
+        parent: Option<usize>,
+        object: Option<usize>
+    ) -> usize {
+        assert!(parent.map_or(true, |parent| parent < self.nodes.len()), "parent node does not exist");
+        let world = parent.map_or(local.clone(), |parent| compose(&self.nodes[parent].world, &local));
+        self.nodes.push(Node { name: name.into(), local, parent, object, world });
+        self.nodes.len() - 1
+    }
+    /// Whether `local` under `parent` can't be placed exactly, see `shears`
+    pub fn shears(&self, parent: Option<usize>, local: &Transform) -> bool {
+        parent.map_or(false, |parent| shears(&self.nodes[parent].world, local))
+    }
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.nodes.iter().position(|node| node.name == name)
+    }
+    pub fn find_object(&self, object: usize) -> Option<usize> {
+        self.nodes.iter().position(|node| node.object == Some(object))
+    }
+    /// Derives the world transforms from the local ones and moves the objects to them
+    pub fn update(&mut self, objects: &[Object]) {
+        let mut done = vec![false;self.nodes.len()];
+        for i in 0..self.nodes.len() {
+            self.resolve(i, &mut done)
+        }
+        for node in self.nodes.iter() {
+            if let Some(object) = node.object {
+                *objects[object].transform.lock().unwrap() = node.world.clone()
+            }
+        }
+    }
+    fn resolve(&mut self, i: usize, done: &mut [bool]) {
+        if done[i] { return }
+        let world = match self.nodes[i].parent {
+            Some(parent) => {
+                self.resolve(parent, done);
+                compose(&self.nodes[parent].world, &self.nodes[i].local)
+            },
+            None => self.nodes[i].local.clone()
+        };
+        self.nodes[i].world = world;
+        done[i] = true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+    use math::{Vec3, Quaternion};
+
+    use super::*;
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).dot(a - b) < 1e-8, "{:?} != {:?}", (a.x, a.y, a.z), (b.x, b.y, b.z))
+    }
+
+    #[test]
+    fn child_follows_parent() {
+        let mut hierarchy = Hierarchy::default();
+        let parent = hierarchy.add("parent", Transform::default(), None, None);
+        let child = hierarchy.add("child", Transform { translation: Vec3::new(1., 0., 0.), ..Default::default() }, Some(parent), None);
+        let grandchild = hierarchy.add("grandchild", Transform { translation: Vec3::new(0., 0., 1.), ..Default::default() }, Some(child), None);
+        assert_near(hierarchy.nodes[grandchild].world.translation, Vec3::new(1., 0., 1.));
+
+        let local = &mut hierarchy.nodes[parent].local;
+        local.translation = Vec3::new(0., 2., 0.);
+        local.rotation = Quaternion::from_angle_y(PI);
+        local.scale = Vec3::new(2., 2., 2.);
+        hierarchy.update(&[]);
+        assert_near(hierarchy.nodes[child].world.translation, Vec3::new(-2., 2., 0.));
+        assert_near(hierarchy.nodes[child].world.scale, Vec3::new(2., 2., 2.));
+        assert_near(hierarchy.nodes[grandchild].world.translation, Vec3::new(-2., 2., -2.));
+    }
+
+    #[test]
+    #[should_panic(expected = "parent node does not exist")]
+    fn add_rejects_unknown_parent() {
+        let mut hierarchy = Hierarchy::default();
+        hierarchy.add("root", Transform::default(), None, None);
+        hierarchy.add("orphan", Transform::default(), Some(1), None);
+    }
+}
@@ -1,7 +1,7 @@
-use std::time::Instant;
+use std::{time::{Instant, Duration}, sync::atomic::Ordering};
 use winit::{event_loop::{EventLoop, ControlFlow}, event::{Event, WindowEvent, KeyboardInput, VirtualKeyCode, ElementState}};
 use winit::platform::run_return::EventLoopExtRunReturn;
-use math::Transform;
+use math::Vec3;
 
 mod engine;
 mod object;
@@ -11,40 +11,71 @@ mod texture;
 mod render;
 mod text;
 mod gimap;
+mod raster;
+mod buffer;
+mod material;
+mod antialiasing;
+mod tonemap;
+mod bounds;
+mod bvh;
+mod debug_draw;
+mod mesh;
+mod error;
+mod hierarchy;
+mod obj;
+mod ply;
+mod stl;
+mod scene;
 
 fn main() {
-    let grass = Box::leak(Box::new(
-        texture::Texture::load("assets/grass.jpg")
-    ));
-    let terraccota = Box::leak(Box::new(
-        texture::Texture::load("assets/terracotta.jpg")
-    ));
-    
-    let objects = Box::leak(Box::new([
-        object::Object::load("assets/cube.gltf", terraccota, Transform::from_translation(3., 0., 0.)),
-        object::Object::load("assets/cube.gltf", terraccota, Transform::from_translation(0., -3., 0.)),
-        object::Object::load("assets/cube.gltf", terraccota, Transform::from_translation(0., 0., 3.)),
-        object::Object::load("assets/cube.gltf", grass, Transform::from_scale(5., 0.01, 5.).with_translation(0., 1.5, 0.))
-    ]));
+    if let Err(error) = run() {
+        eprintln!("error: {error}");
+        std::process::exit(1)
+    }
+}
+
+fn run() -> error::Result<()> {
+    // A scene file, or a glTF scene or OBJ, PLY or STL model to add to the default scene, given as the first argument
+    let arg = std::env::args().nth(1);
+    let is_scene = |path: &String| object::has_extension(path.as_ref(), "json");
+    let mut scene = scene::Scene::load(arg.as_ref().filter(|path| is_scene(path)).map_or("assets/scene.json", String::as_str))?;
+    let import_log = text::Log::default();
+    if let Some(path) = arg.filter(|path| !is_scene(path)) {
+        let first = scene.objects.len();
+        scene.import(&path)?;
+        let objects = &scene.objects[first..];
+        let triangles = objects.iter().map(|object| object.model.mesh.indices.len()).sum::<usize>();
+        import_log.set(format!("Loaded {} objects, {triangles} triangles", objects.len()))
+    }
+    let gi_enabled = scene.gi.enabled;
     
     let gi_log = text::Log::default();
-    let _gi_log = gi_log.clone();
     let render_log = text::Log::default();
-    let logs = vec![render_log.clone(), gi_log];
+    let logs = vec![render_log.clone(), gi_log.clone(), import_log];
 
     let mut event_loop = EventLoop::new();
-    let mut engine = engine::Engine::new(&event_loop, objects, logs);
+    let mut engine = engine::Engine::new(&event_loop, scene, logs)?;
     
-    std::thread::spawn(|| {
-        let gi_log = _gi_log;
-        loop {
-            let start = Instant::now();
-            for object in objects.iter() {
-                object.gimap.update(objects, engine.dir_light)
+    if gi_enabled {
+        let (objects, dir_light) = (engine.objects, engine.dir_light);
+        std::thread::spawn(move || {
+            let mut occluders = gimap::Occluders::new(objects);
+            loop {
+                if objects.iter().all(|object| object.gimap.is_baked()) {
+                    std::thread::sleep(Duration::from_millis(10));
+                    continue
+                }
+                let start = Instant::now();
+                if occluders.generation != gimap::GENERATION.load(Ordering::Acquire) {
+                    occluders = gimap::Occluders::new(objects)
+                }
+                for (i, object) in objects.iter().enumerate() {
+                    object.gimap.update(i, objects, &occluders, dir_light)
+                }
+                gi_log.set(format!("GI {}ms", (Instant::now() - start).as_millis()));
             }
-            gi_log.set(format!("GI {}ms", (Instant::now() - start).as_millis()));
-        }
-    });
+        });
+    }
     
     event_loop.run_return(move |e, _, control_flow| {
         match e {
@@ -69,6 +100,20 @@ fn main() {
                             VirtualKeyCode::E => engine.camera.rotation.x += 0.1,
                             VirtualKeyCode::R => engine.camera.translation.y += 0.1,
                             VirtualKeyCode::F => engine.camera.translation.y -= 0.1,
+                            VirtualKeyCode::F1 => engine.anti_aliasing = engine.anti_aliasing.next(),
+                            VirtualKeyCode::F2 => engine.tone_mapper.operator = engine.tone_mapper.operator.next(),
+                            VirtualKeyCode::F3 => engine.tone_mapper.dither = !engine.tone_mapper.dither,
+                            VirtualKeyCode::F4 => engine.view_mode = engine.view_mode.next(),
+                            VirtualKeyCode::F5 => engine.wireframe = !engine.wireframe,
+                            VirtualKeyCode::F6 => engine.show_helpers = !engine.show_helpers,
+                            VirtualKeyCode::F7 => engine.animate = !engine.animate,
+                            VirtualKeyCode::F8 => engine.show_bvh = !engine.show_bvh,
+                            VirtualKeyCode::Equals => engine.tone_mapper.exposure += 0.25,
+                            VirtualKeyCode::Minus => engine.tone_mapper.exposure -= 0.25,
+                            VirtualKeyCode::Left => engine.move_node(Vec3::new(-0.1, 0., 0.)),
+                            VirtualKeyCode::Right => engine.move_node(Vec3::new(0.1, 0., 0.)),
+                            VirtualKeyCode::Up => engine.move_node(Vec3::new(0., 0., -0.1)),
+                            VirtualKeyCode::Down => engine.move_node(Vec3::new(0., 0., 0.1)),
                             _ => {}
                         }
                     }
@@ -77,4 +122,5 @@ fn main() {
             _ => {}
         }
     });
-}
\ No newline at end of file
+    Ok(())
+}
@@ -0,0 +1,192 @@
+use std::f32::consts::PI;
+use math::{Vec2, Vec3, Vec4};
+
+use crate::{texture::{Texture, Sampler, ColorSpace}, error::Result};
+
+// Constant ambient term, scaled by the occlusion map
+const AMBIENT: f32 = 0.03;
+
+#[derive(Clone, Copy)]
+pub struct TextureBinding {
+    pub texture: &'static Texture,
+    pub sampler: Sampler
+}
+impl TextureBinding {
+    #[inline(always)]
+    pub fn sample(&self, uv: Vec2, duvdx: Vec2, duvdy: Vec2) -> Vec4 {
+        self.texture.sample(&self.sampler, uv, self.texture.lod(duvdx, duvdy))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum AlphaMode {
+    Opaque,
+    /// Fragments with an alpha below the cutoff are discarded
+    Mask(f32),
+    /// Blended over what is behind, drawn back to front after the opaque surfaces
+    Blend
+}
+
+#[derive(Clone)]
+pub struct Material {
+    pub base_color: Vec4,
+    pub base_color_texture: Option<TextureBinding>,
+    pub metallic: f32,
+    pub roughness: f32,
+    /// Roughness in the green channel, metalness in the blue channel
+    pub metallic_roughness_texture: Option<TextureBinding>,
+    pub normal_texture: Option<TextureBinding>,
+    pub normal_scale: f32,
+    pub occlusion_texture: Option<TextureBinding>,
+    pub occlusion_strength: f32,
+    pub emissive: Vec3,
+    pub emissive_texture: Option<TextureBinding>,
+    pub alpha_mode: AlphaMode
+}
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            base_color: Vec4::new(1., 1., 1., 1.),
+            base_color_texture: None,
+            metallic: 0.,
+            roughness: 1.,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            normal_scale: 1.,
+            occlusion_texture: None,
+            occlusion_strength: 1.,
+            emissive: Vec3::default(),
+            emissive_texture: None,
+            alpha_mode: AlphaMode::Opaque
+        }
+    }
+}
+impl Material {
+    /// `resolve` maps a glTF texture to its image decoded in the given color space,
+    /// slots it can not resolve are left empty
+    pub fn from_gltf(
+        material: &gltf::Material,
+        resolve: impl Fn(&gltf::Texture, ColorSpace) -> Result<Option<&'static Texture>>
+    ) -> Result<Self> {
+        let bind = |texture: gltf::Texture, color_space| -> Result<Option<TextureBinding>> {
+            Ok(resolve(&texture, color_space)?.map(|image| TextureBinding {
+                texture: image,
+                sampler: Sampler::from_gltf(&texture.sampler())
+            }))
+        };
+        let pbr = material.pbr_metallic_roughness();
+        let [r, g, b, a] = pbr.base_color_factor();
+        Ok(Self {
+            base_color: Vec4::new(r, g, b, a),
+            base_color_texture: pbr.base_color_texture().map(|info| bind(info.texture(), ColorSpace::Srgb)).transpose()?.flatten(),
+            metallic: pbr.metallic_factor(),
+            roughness: pbr.roughness_factor(),
+            metallic_roughness_texture: pbr.metallic_roughness_texture().map(|info| bind(info.texture(), ColorSpace::Linear)).transpose()?.flatten(),
+            normal_texture: material.normal_texture().map(|normal| bind(normal.texture(), ColorSpace::Linear)).transpose()?.flatten(),
+            normal_scale: material.normal_texture().map_or(1., |normal| normal.scale()),
+            occlusion_texture: material.occlusion_texture().map(|occlusion| bind(occlusion.texture(), ColorSpace::Linear)).transpose()?.flatten(),
+            occlusion_strength: material.occlusion_texture().map_or(1., |occlusion| occlusion.strength()),
+            emissive: material.emissive_factor().into(),
+            emissive_texture: material.emissive_texture().map(|info| bind(info.texture(), ColorSpace::Srgb)).transpose()?.flatten(),
+            alpha_mode: match material.alpha_mode() {
+                gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+                gltf::material::AlphaMode::Mask => AlphaMode::Mask(material.alpha_cutoff().unwrap_or(0.5)),
+                gltf::material::AlphaMode::Blend => AlphaMode::Blend
+            }
+        })
+    }
+    /// Fraction of the light passing through a surface with the sampled `alpha`
+    #[inline(always)]
+    pub fn transmittance(&self, alpha: f32) -> f32 {
+        match self.alpha_mode {
+            AlphaMode::Opaque => 0.,
+            AlphaMode::Mask(cutoff) => if alpha < cutoff { 1. } else { 0. },
+            AlphaMode::Blend => 1. - alpha
+        }
+    }
+    pub fn surface(&self, uv: Vec2, duvdx: Vec2, duvdy: Vec2) -> Surface {
+        let sample = |binding: &Option<TextureBinding>| binding.as_ref().map(|binding| binding.sample(uv, duvdx, duvdy));
+        let base = self.base_color;
+        let base = sample(&self.base_color_texture)
+            .map_or(base, |c| Vec4::new(c.x * base.x, c.y * base.y, c.z * base.z, c.w * base.w));
+        let (metallic, roughness) = sample(&self.metallic_roughness_texture)
+            .map_or((self.metallic, self.roughness), |c| (c.z * self.metallic, c.y * self.roughness));
+        Surface {
+            albedo: Vec3::new(base.x, base.y, base.z),
+            alpha: base.w,
+            metallic,
+            roughness: roughness.max(0.04),
+            occlusion: sample(&self.occlusion_texture)
+                .map_or(1., |c| 1. + self.occlusion_strength * (c.x - 1.)),
+            emissive: sample(&self.emissive_texture)
+                .map_or(self.emissive, |c| mul(Vec3::new(c.x, c.y, c.z), self.emissive)),
+            normal: sample(&self.normal_texture).map(|c| Vec3::new(
+                (c.x * 2. - 1.) * self.normal_scale,
+                (c.y * 2. - 1.) * self.normal_scale,
+                c.z * 2. - 1.
+            ))
+        }
+    }
+}
+
+/// Material sampled at a single point
+pub struct Surface {
+    pub albedo: Vec3,
+    pub alpha: f32,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub occlusion: f32,
+    pub emissive: Vec3,
+    /// Tangent space normal from the normal map
+    pub normal: Option<Vec3>
+}
+impl Surface {
+    #[inline(always)]
+    fn f0(&self) -> Vec3 {
+        let dielectric = 0.04 * (1. - self.metallic);
+        self.albedo * self.metallic + Vec3::new(dielectric, dielectric, dielectric)
+    }
+    /// Share of the incoming light reflected diffusely, it does not depend on the view direction
+    /// so it is what the lightmap stores, the albedo is applied when shading
+    #[inline(always)]
+    pub fn diffuse_weight(&self, n_dot_l: f32) -> f32 {
+        if n_dot_l <= 0. { return 0. }
+        let f = fresnel(self.f0(), n_dot_l);
+        (1. - (f.x + f.y + f.z) / 3.) * (1. - self.metallic) * n_dot_l
+    }
+    #[inline(always)]
+    pub fn ambient(&self) -> Vec3 {
+        self.albedo * (AMBIENT * self.occlusion)
+    }
+    /// Cook-Torrance with a GGX distribution, Smith-Schlick geometry and Schlick fresnel.
+    /// Returns the diffuse and specular light reflected towards `v` from a directional light `l`
+    /// whose Lambert response is `albedo * n.l`
+    #[inline(always)]
+    pub fn cook_torrance(&self, n: Vec3, v: Vec3, l: Vec3) -> (Vec3, Vec3) {
+        let n_dot_l = n.dot(l);
+        let n_dot_v = n.dot(v).max(1e-4);
+        if n_dot_l <= 0. { return (Vec3::default(), Vec3::default()) }
+        let h = (v + l).normalized();
+        let n_dot_h = n.dot(h).max(0.);
+        let a = self.roughness * self.roughness;
+        let a2 = a * a;
+        let d = n_dot_h * n_dot_h * (a2 - 1.) + 1.;
+        let distribution = a2 / (PI * d * d);
+        let k = (self.roughness + 1.) * (self.roughness + 1.) / 8.;
+        let geometry = n_dot_v / (n_dot_v * (1. - k) + k) * n_dot_l / (n_dot_l * (1. - k) + k);
+        let f = fresnel(self.f0(), v.dot(h).max(0.));
+        let specular = f * (distribution * geometry / (4. * n_dot_v * n_dot_l) * PI * n_dot_l);
+        let kd = Vec3::new(1. - f.x, 1. - f.y, 1. - f.z) * (1. - self.metallic);
+        (mul(kd, self.albedo) * n_dot_l, specular)
+    }
+}
+
+#[inline(always)]
+fn fresnel(f0: Vec3, cos: f32) -> Vec3 {
+    let t = (1. - cos).powi(5);
+    Vec3::new(f0.x + (1. - f0.x) * t, f0.y + (1. - f0.y) * t, f0.z + (1. - f0.z) * t)
+}
+#[inline(always)]
+pub fn mul(a: Vec3, b: Vec3) -> Vec3 {
+    Vec3::new(a.x * b.x, a.y * b.y, a.z * b.z)
+}
@@ -0,0 +1,311 @@
+use std::collections::HashMap;
+use math::{Vec3, Vec2};
+
+use crate::{render::cross, bounds::Aabb};
+
+#[derive(Clone, Copy)]
+pub struct Vertex {
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub uv: Vec2,
+    /// Linear, multiplies the base color of the material
+    pub color: Vec3
+}
+impl Vertex {
+    /// Exact bit pattern, identical vertices share a key
+    fn key(&self) -> [u32;11] {
+        [
+            self.position.x.to_bits(), self.position.y.to_bits(), self.position.z.to_bits(),
+            self.normal.x.to_bits(), self.normal.y.to_bits(), self.normal.z.to_bits(),
+            self.uv.x.to_bits(), self.uv.y.to_bits(),
+            self.color.x.to_bits(), self.color.y.to_bits(), self.color.z.to_bits()
+        ]
+    }
+}
+
+/// Marks a triangle edge without a neighbor in `Mesh::adjacency`
+pub const NO_NEIGHBOR: u32 = u32::MAX;
+
+// Lightmap texels along the side of each `Mesh::unwrap_uvs` cell
+const UNWRAP_CELL_TEXELS: usize = 8;
+// Largest lightmap side asked for by `Mesh::unwrap_uvs`, cells of bigger meshes shrink
+const UNWRAP_MAX_SIZE: usize = 2048;
+
+/// Indexed triangle mesh
+#[derive(Clone, Default)]
+pub struct Mesh {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<[u32;3]>,
+    /// Per triangle, the triangle across each edge `(0 1)`, `(1 2)` and `(2 0)`,
+    /// edges are matched by position so uv and normal seams do not split them
+    pub adjacency: Option<Vec<[u32;3]>>
+}
+impl Mesh {
+    /// Welds identical vertices, the triangle order is kept
+    pub fn new(vertices: Vec<Vertex>, indices: Vec<[u32;3]>) -> Self {
+        let mut welded = Vec::new();
+        let mut lookup = HashMap::new();
+        let remap = vertices.iter()
+            .map(|vertex| *lookup.entry(vertex.key()).or_insert_with(|| {
+                welded.push(*vertex);
+                welded.len() as u32 - 1
+            }))
+            .collect::<Vec<_>>();
+        Self {
+            vertices: welded,
+            indices: indices.iter().map(|triangle| triangle.map(|i| remap[i as usize])).collect(),
+            adjacency: None
+        }
+    }
+    pub fn with_adjacency(mut self) -> Self {
+        self.compute_adjacency();
+        self
+    }
+    pub fn compute_adjacency(&mut self) {
+        let mut positions = HashMap::new();
+        let position_ids = self.vertices.iter()
+            .map(|vertex| {
+                let p = vertex.position;
+                let next = positions.len() as u32;
+                *positions.entry([p.x.to_bits(), p.y.to_bits(), p.z.to_bits()]).or_insert(next)
+            })
+            .collect::<Vec<_>>();
+        let edge = |triangle: &[u32;3], e: usize| {
+            let (a, b) = (position_ids[triangle[e] as usize], position_ids[triangle[(e + 1) % 3] as usize]);
+            (a.min(b), a.max(b))
+        };
+        let mut edges = HashMap::<(u32, u32), Vec<(u32, usize)>>::new();
+        for (t, triangle) in self.indices.iter().enumerate() {
+            for e in 0..3 {
+                edges.entry(edge(triangle, e)).or_default().push((t as u32, e))
+            }
+        }
+        let mut adjacency = vec![[NO_NEIGHBOR;3];self.indices.len()];
+        for shared in edges.values() {
+            // Only manifold edges get a neighbor
+            if let [(t0, e0), (t1, e1)] = shared[..] {
+                adjacency[t0 as usize][e0] = t1;
+                adjacency[t1 as usize][e1] = t0
+            }
+        }
+        self.adjacency = Some(adjacency)
+    }
+    /// Angle weighted average of the normals of the triangles sharing each vertex,
+    /// vertices used by a single triangle get its flat normal
+    pub fn smooth_normals(&mut self) {
+        let mut normals = vec![Vec3::default();self.vertices.len()];
+        for triangle in self.indices.iter() {
+            let p = triangle.map(|i| self.vertices[i as usize].position);
+            let face = cross(p[1] - p[0], p[2] - p[0]);
+            let length = face.dot(face).sqrt();
+            if length <= 0. { continue }
+            let face = face * (1. / length);
+            for k in 0..3 {
+                let e1 = (p[(k + 1) % 3] - p[k]).normalized();
+                let e2 = (p[(k + 2) % 3] - p[k]).normalized();
+                let angle = e1.dot(e2).max(-1.).min(1.).acos();
+                let i = triangle[k] as usize;
+                normals[i] = normals[i] + face * angle
+            }
+        }
+        for (vertex, normal) in self.vertices.iter_mut().zip(normals) {
+            vertex.normal = if normal.dot(normal) > 0. { normal.normalized() } else { Vec3::new(0., 1., 0.) }
+        }
+    }
+    /// Planar projection along the dominant axis of each vertex normal, the bounds are scaled to fit `[0, 1]`
+    pub fn project_uvs(&mut self) {
+        let bounds = Aabb::from_points(self.vertices.iter().map(|vertex| vertex.position));
+        let size = bounds.max - bounds.min;
+        let scale = 1. / size.x.max(size.y).max(size.z).max(1e-6);
+        for vertex in self.vertices.iter_mut() {
+            let p = (vertex.position - bounds.min) * scale;
+            let n = Vec3::new(vertex.normal.x.abs(), vertex.normal.y.abs(), vertex.normal.z.abs());
+            vertex.uv = if n.x >= n.y && n.x >= n.z {
+                Vec2::new(p.z, p.y)
+            } else if n.y >= n.z {
+                Vec2::new(p.x, p.z)
+            } else {
+                Vec2::new(p.x, p.y)
+            }
+        }
+    }
+    /// Lightmap uvs for meshes without any, every triangle fills its own cell of a square grid
+    /// so no two triangles share texels and each covers some. Vertices are split per triangle.
+    /// Returns the lightmap side in texels aligning `UNWRAP_CELL_TEXELS` texels with each cell
+    pub fn unwrap_uvs(&mut self) -> usize {
+        let grid = (self.indices.len() as f32).sqrt().ceil().max(1.);
+        let cell = 1. / grid;
+        let padding = cell * 0.1;
+        let mut vertices = Vec::with_capacity(self.indices.len() * 3);
+        for (t, triangle) in self.indices.iter_mut().enumerate() {
+            let v = triangle.map(|i| self.vertices[i as usize]);
+            let p = v.map(|vertex| vertex.position);
+            // Flattened in the plane of the triangle, its longest edge along u
+            let lengths = [0, 1, 2].map(|k| { let e = p[(k + 1) % 3] - p[k]; e.dot(e) });
+            let k = if lengths[0] >= lengths[1] && lengths[0] >= lengths[2] { 0 } else if lengths[1] >= lengths[2] { 1 } else { 2 };
+            let origin = p[k];
+            let u = (p[(k + 1) % 3] - origin).normalized();
+            let normal = cross(p[1] - p[0], p[2] - p[0]);
+            let w = if normal.dot(normal) > 0. { cross(normal.normalized(), u) } else { Vec3::default() };
+            let flat = p.map(|p| Vec2::new((p - origin).dot(u), (p - origin).dot(w)));
+            let min = Vec2::new(flat[0].x.min(flat[1].x).min(flat[2].x), flat[0].y.min(flat[1].y).min(flat[2].y));
+            let max = Vec2::new(flat[0].x.max(flat[1].x).max(flat[2].x), flat[0].y.max(flat[1].y).max(flat[2].y));
+            // Stretched on each axis to fill the cell, slivers still cover texel centers
+            let scale = Vec2::new(
+                (cell - padding * 2.) / (max.x - min.x).max(1e-12),
+                (cell - padding * 2.) / (max.y - min.y).max(1e-12)
+            );
+            let corner = Vec2::new((t as f32 % grid) * cell + padding, (t as f32 / grid).floor() * cell + padding);
+            for i in 0..3 {
+                let f = flat[i];
+                vertices.push(Vertex { uv: Vec2::new(corner.x + (f.x - min.x) * scale.x, corner.y + (f.y - min.y) * scale.y), ..v[i] });
+                triangle[i] = vertices.len() as u32 - 1
+            }
+        }
+        self.vertices = vertices;
+        (grid as usize * UNWRAP_CELL_TEXELS).min(UNWRAP_MAX_SIZE)
+    }
+    #[inline(always)]
+    pub fn triangle(&self, triangle_id: usize) -> [Vertex;3] {
+        self.indices[triangle_id].map(|i| self.vertices[i as usize])
+    }
+}
+
+/// Ear clipping in the plane of the polygon, the rest is fanned out when no ear is left (self intersecting polygons)
+pub fn triangulate(points: &[Vec3]) -> Vec<[usize;3]> {
+    // Newell's method, robust to concave and slightly non planar polygons
+    let n = points.len();
+    let normal = (0..n).fold(Vec3::default(), |normal, i| {
+        let (a, b) = (points[i], points[(i + 1) % n]);
+        normal + Vec3::new((a.y - b.y) * (a.z + b.z), (a.z - b.z) * (a.x + b.x), (a.x - b.x) * (a.y + b.y))
+    });
+    let inside = |p: Vec3, a: Vec3, b: Vec3, c: Vec3| {
+        cross(b - a, p - a).dot(normal) >= 0. && cross(c - b, p - b).dot(normal) >= 0. && cross(a - c, p - c).dot(normal) >= 0.
+    };
+    let mut remaining = (0..n).collect::<Vec<_>>();
+    let mut triangles = Vec::new();
+    while remaining.len() > 3 {
+        let m = remaining.len();
+        let corner = |i: usize| [remaining[(i + m - 1) % m], remaining[i], remaining[(i + 1) % m]];
+        let ear = (0..m).find(|&i| {
+            let [a, b, c] = corner(i);
+            cross(points[b] - points[a], points[c] - points[b]).dot(normal) > 0.
+                && remaining.iter().all(|&j| j == a || j == b || j == c || !inside(points[j], points[a], points[b], points[c]))
+        });
+        let Some(ear) = ear else { break };
+        triangles.push(corner(ear));
+        remaining.remove(ear);
+    }
+    triangles.extend((1..remaining.len() - 1).map(|i| [remaining[0], remaining[i], remaining[i + 1]]));
+    triangles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(x: f32, y: f32, z: f32) -> Vertex {
+        Vertex { position: Vec3::new(x, y, z), normal: Vec3::new(0., 0., 1.), uv: Vec2::new(x, y), color: Vec3::new(1., 1., 1.) }
+    }
+
+    #[test]
+    fn welds_quads_sharing_an_edge() {
+        let vertices = [(0., 0.), (1., 0.), (1., 1.), (0., 1.), (1., 0.), (2., 0.), (2., 1.), (1., 1.)]
+            .map(|(x, y)| vertex(x, y, 0.))
+            .to_vec();
+        let mesh = Mesh::new(vertices, vec![[0, 1, 2], [0, 2, 3], [4, 5, 6], [4, 6, 7]]).with_adjacency();
+        assert_eq!(mesh.vertices.len(), 6);
+        assert_eq!(mesh.indices, vec![[0, 1, 2], [0, 2, 3], [1, 4, 5], [1, 5, 2]]);
+        let adjacency = mesh.adjacency.unwrap();
+        assert_eq!(adjacency[0], [NO_NEIGHBOR, 3, 1]);
+        assert_eq!(adjacency[3], [2, NO_NEIGHBOR, 0]);
+    }
+
+    #[test]
+    fn uv_seams_stay_split_and_adjacent() {
+        let mut vertices = [(0., 0.), (1., 0.), (1., 1.), (1., 0.), (2., 0.), (1., 1.)]
+            .map(|(x, y)| vertex(x, y, 0.))
+            .to_vec();
+        vertices[3].uv = Vec2::new(0.5, 0.);
+        vertices[5].uv = Vec2::new(0.5, 1.);
+        let mesh = Mesh::new(vertices, vec![[0, 1, 2], [3, 4, 5]]).with_adjacency();
+        assert_eq!(mesh.vertices.len(), 6);
+        let adjacency = mesh.adjacency.unwrap();
+        assert_eq!(adjacency[0], [NO_NEIGHBOR, 1, NO_NEIGHBOR]);
+        assert_eq!(adjacency[1], [NO_NEIGHBOR, NO_NEIGHBOR, 0]);
+    }
+
+    #[test]
+    fn fin_edges_have_no_neighbor() {
+        // Three triangles on the edge 0 1, the last one also shares the edge 1 2 with the first
+        let vertices = [(0., 0., 0.), (1., 0., 0.), (0., 1., 0.), (0., -1., 0.), (0., 0., 1.), (1., 1., 1.)]
+            .map(|(x, y, z)| vertex(x, y, z))
+            .to_vec();
+        let mesh = Mesh::new(vertices, vec![[0, 1, 2], [1, 0, 3], [0, 1, 4], [2, 1, 5]]).with_adjacency();
+        let adjacency = mesh.adjacency.unwrap();
+        for triangle in 0..3 {
+            assert_eq!(adjacency[triangle][0], NO_NEIGHBOR)
+        }
+        assert_eq!(adjacency[0][1], 3);
+        assert_eq!(adjacency[3][0], 0);
+    }
+
+    /// Every triangle wound like the polygon, together covering its area
+    fn assert_triangulated(points: &[Vec3]) {
+        let area = |a: Vec3, b: Vec3, c: Vec3| cross(b - a, c - a).z * 0.5;
+        let polygon = (0..points.len()).map(|i| {
+            let (a, b) = (points[i], points[(i + 1) % points.len()]);
+            (a.x * b.y - b.x * a.y) * 0.5
+        }).sum::<f32>();
+        let triangles = triangulate(points);
+        assert_eq!(triangles.len(), points.len() - 2);
+        for &[a, b, c] in triangles.iter() {
+            assert!(area(points[a], points[b], points[c]) * polygon.signum() > 0., "triangle {a} {b} {c} flipped or degenerate")
+        }
+        let covered = triangles.iter().map(|&[a, b, c]| area(points[a], points[b], points[c])).sum::<f32>();
+        assert!((covered - polygon).abs() < 1e-4)
+    }
+
+    #[test]
+    fn triangulates_concave_polygons() {
+        // Star starting at a tip, a fan from it would cross the outside
+        let star = (0..10)
+            .map(|i| {
+                let angle = i as f32 * std::f32::consts::PI / 5.;
+                let radius = if i % 2 == 0 { 2. } else { 0.8 };
+                Vec3::new(angle.cos() * radius, angle.sin() * radius, 0.)
+            })
+            .collect::<Vec<_>>();
+        assert_triangulated(&star);
+        assert_triangulated(&star.iter().rev().copied().collect::<Vec<_>>());
+        // Comb with two reflex corners
+        let comb = [(0., 0.), (3., 0.), (3., 2.), (2., 2.), (2., 1.), (1., 1.), (1., 2.), (0., 2.)]
+            .map(|(x, y)| Vec3::new(x, y, 0.));
+        assert_triangulated(&comb);
+    }
+
+    #[test]
+    fn unwrapped_triangles_each_own_a_texel() {
+        // Slivers as found in CAD exports, long and a thousandth as tall, in every orientation
+        let mut vertices = Vec::new();
+        for i in 0..300 {
+            let angle = i as f32 * 0.37;
+            let (s, c) = angle.sin_cos();
+            let length = 1. + (i % 7) as f32;
+            let apex = 0.1 + (i % 5) as f32 * 0.2;
+            let along = Vec3::new(c, s, (i % 3) as f32 * 0.3).normalized() * length;
+            let across = cross(along, Vec3::new(0., 0., 1.)).normalized() * (length * 1e-3);
+            let origin = Vec3::new(i as f32, 0., 0.);
+            vertices.extend([origin, origin + along, origin + along * apex + across].map(|p| vertex(p.x, p.y, p.z)))
+        }
+        let indices = (0..300).map(|t| [t * 3, t * 3 + 1, t * 3 + 2]).collect();
+        let mut mesh = Mesh::new(vertices, indices);
+        let size = mesh.unwrap_uvs() as f32;
+        for t in 0..mesh.indices.len() {
+            let [a, b, c] = mesh.triangle(t).map(|vertex| Vec2::new(vertex.uv.x * size, vertex.uv.y * size));
+            let mut texels = 0;
+            crate::raster::raster_triangle(0, 0, size as i32, size as i32, a, b, c, |_, _, _| texels += 1);
+            assert!(texels > 0, "triangle {t} covers no texel")
+        }
+    }
+}
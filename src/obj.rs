@@ -0,0 +1,276 @@
+use std::{path::{Path, PathBuf}, collections::HashMap};
+use math::{Vec2, Vec3, Vec4};
+
+use crate::{
+    object::{Model, Primitive},
+    mesh::{Mesh, Vertex, triangulate},
+    material::{Material, TextureBinding, AlphaMode},
+    texture::{Texture, Sampler, ColorSpace},
+    error::{Error, Result}
+};
+
+/// Faces of a group using a single material
+struct Part {
+    material: usize,
+    vertices: Vec<Vertex>,
+    indices: Vec<[u32;3]>,
+    missing_normals: bool,
+    missing_uvs: bool
+}
+
+/// Loads a Wavefront OBJ file and its MTL libraries as a single model, one primitive per group and material pair.
+/// Polygons are triangulated, `texture` stands in for missing `map_Kd` images. Missing libraries, images and
+/// materials only print a warning, the faces fall back to the default material
+pub fn load(path: &Path, texture: Option<&'static Texture>) -> Result<Model> {
+    let source = std::fs::read_to_string(path).map_err(|error| Error::from(error).in_file(path))?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    let mut library = Library::default();
+    let (mut positions, mut normals, mut uvs) = (Vec::<Vec3>::new(), Vec::<Vec3>::new(), Vec::<Vec2>::new());
+    let mut parts = Vec::<Part>::new();
+    let mut part_ids = HashMap::new();
+    let mut group = String::new();
+    let mut material = None;
+    let mut skipped = 0;
+    for (line, text) in source.lines().enumerate() {
+        let at = || format!("line {}", line + 1);
+        let mut tokens = text.split_whitespace();
+        let Some(keyword) = tokens.next() else { continue };
+        let args = tokens.collect::<Vec<_>>();
+        match keyword {
+            "v" => {
+                let [x, y, z] = floats(&args, path, at)?;
+                positions.push(Vec3::new(x, y, z))
+            },
+            "vn" => {
+                let [x, y, z] = floats(&args, path, at)?;
+                normals.push(Vec3::new(x, y, z))
+            },
+            "vt" => {
+                // The second coordinate is optional, images start at the top unlike OBJ
+                let [u] = floats(&args, path, at)?;
+                let v = args.get(1).and_then(|v| v.parse::<f32>().ok()).unwrap_or(0.);
+                uvs.push(Vec2::new(u, 1. - v))
+            },
+            "f" => {
+                if args.len() < 3 {
+                    return Err(Error::validation("face with less than 3 vertices").in_file(path).at(at()))
+                }
+                let corners = args.iter()
+                    .map(|corner| {
+                        let mut refs = corner.split('/');
+                        let position = index(refs.next(), positions.len(), path, at)?
+                            .ok_or_else(|| Error::validation("face vertex without a position").in_file(path).at(at()))?;
+                        let uv = index(refs.next(), uvs.len(), path, at)?;
+                        let normal = index(refs.next(), normals.len(), path, at)?;
+                        Ok((positions[position], uv.map(|i| uvs[i]), normal.map(|i| normals[i])))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+
+                let material = material.unwrap_or(usize::MAX);
+                let part = *part_ids.entry((group.clone(), material)).or_insert_with(|| {
+                    parts.push(Part { material, vertices: Vec::new(), indices: Vec::new(), missing_normals: false, missing_uvs: false });
+                    parts.len() - 1
+                });
+                let part = &mut parts[part];
+                let offset = part.vertices.len() as u32;
+                for &(position, uv, normal) in corners.iter() {
+                    part.missing_normals |= normal.is_none();
+                    part.missing_uvs |= uv.is_none();
+                    part.vertices.push(Vertex {
+                        position,
+                        normal: normal.unwrap_or_default(),
+                        uv: uv.unwrap_or_default(),
+                        color: Vec3::new(1., 1., 1.)
+                    })
+                }
+                let points = corners.iter().map(|corner| corner.0).collect::<Vec<_>>();
+                part.indices.extend(triangulate(&points).into_iter().map(|triangle| triangle.map(|i| offset + i as u32)))
+            },
+            "g" | "o" => group = args.join(" "),
+            "usemtl" => {
+                // Without a name the default material is used again
+                let name = args.join(" ");
+                material = if name.is_empty() { None } else { library.ids.get(&name).copied() };
+                if !name.is_empty() && material.is_none() {
+                    eprintln!("warning: {}, default material used", Error::validation(format!("unknown material {name}")).in_file(path).at(at()))
+                }
+            },
+            "mtllib" => for file in args {
+                let file = dir.join(file);
+                match std::fs::read_to_string(&file) {
+                    Ok(source) => library.load(&source, &file, texture)?,
+                    Err(error) => eprintln!("warning: {}, skipped", Error::from(error).in_file(&file))
+                }
+            },
+            "l" | "p" => skipped += 1,
+            _ => {}
+        }
+    }
+    if skipped > 0 {
+        eprintln!("warning: {}, skipped", Error::unsupported(format!("{skipped} line and point elements")).in_file(path))
+    }
+    if parts.is_empty() {
+        return Err(Error::validation("mesh has no triangles").in_file(path))
+    }
+
+    // Faces without a material use a default one, last
+    let default = library.materials.len();
+    library.materials.push(Material {
+        base_color_texture: texture.map(|texture| TextureBinding { texture, sampler: Sampler::default() }),
+        ..Default::default()
+    });
+
+    let (mut vertices, mut indices, mut primitives) = (Vec::new(), Vec::new(), Vec::new());
+    for part in parts {
+        let mut mesh = Mesh::new(part.vertices, part.indices);
+        if part.missing_normals { mesh.smooth_normals() }
+        if part.missing_uvs { mesh.project_uvs() }
+        let offset = vertices.len() as u32;
+        primitives.push(Primitive {
+            triangles: indices.len()..indices.len() + mesh.indices.len(),
+            material: if part.material == usize::MAX { default } else { part.material }
+        });
+        indices.extend(mesh.indices.iter().map(|triangle| triangle.map(|i| i + offset)));
+        vertices.extend(mesh.vertices)
+    }
+    let name = path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+    Ok(Model::new(name, Mesh::new(vertices, indices).with_adjacency(), primitives, library.materials))
+}
+
+/// Materials of the MTL files referenced so far
+#[derive(Default)]
+struct Library {
+    materials: Vec<Material>,
+    ids: HashMap<String, usize>,
+    /// Images are decoded once per color space they are sampled in
+    textures: HashMap<(PathBuf, ColorSpace), &'static Texture>
+}
+impl Library {
+    /// `source` is the content of the MTL file at `path`
+    fn load(&mut self, source: &str, path: &Path, texture: Option<&'static Texture>) -> Result<()> {
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let first = self.materials.len();
+        for (line, text) in source.lines().enumerate() {
+            let at = || format!("line {}", line + 1);
+            let mut tokens = text.split_whitespace();
+            let Some(keyword) = tokens.next() else { continue };
+            let args = tokens.collect::<Vec<_>>();
+            if keyword == "newmtl" {
+                self.ids.insert(args.join(" "), self.materials.len());
+                self.materials.push(Material::default());
+                continue
+            }
+            let Some(material) = self.materials[first..].last_mut() else { continue };
+            let mut map = |color_space| bind(&mut self.textures, dir, &args, color_space).unwrap_or_else(|error| {
+                eprintln!("warning: {error}, skipped");
+                None
+            });
+            match keyword {
+                "Kd" => {
+                    let [r, g, b] = floats(&args, path, at)?;
+                    material.base_color = Vec4::new(r, g, b, material.base_color.w)
+                },
+                "d" => material.base_color.w = floats(&args, path, at).map(|[d]| d)?,
+                "Tr" => material.base_color.w = floats(&args, path, at).map(|[tr]| 1. - tr)?,
+                "Ke" => material.emissive = floats(&args, path, at).map(|[r, g, b]| Vec3::new(r, g, b))?,
+                // Blinn-Phong exponent, a following `Pr` replaces it
+                "Ns" => material.roughness = floats(&args, path, at).map(|[ns]: [f32;1]| (2. / (ns.max(0.) + 2.)).sqrt())?,
+                "Pr" => material.roughness = floats(&args, path, at).map(|[pr]| pr)?,
+                "Pm" => material.metallic = floats(&args, path, at).map(|[pm]| pm)?,
+                "map_Kd" => material.base_color_texture = map(ColorSpace::Srgb),
+                "map_Ke" => material.emissive_texture = map(ColorSpace::Srgb),
+                // Exporters write tangent space normal maps to either
+                "norm" | "map_Bump" | "bump" => {
+                    material.normal_texture = map(ColorSpace::Linear);
+                    if let Some(i) = args.iter().position(|&arg| arg == "-bm") {
+                        material.normal_scale = args.get(i + 1).and_then(|scale| scale.parse().ok()).unwrap_or(1.)
+                    }
+                },
+                _ => {}
+            }
+        }
+        for material in self.materials[first..].iter_mut() {
+            if material.base_color.w < 1. { material.alpha_mode = AlphaMode::Blend }
+            if material.emissive_texture.is_some() && material.emissive.dot(material.emissive) == 0. {
+                material.emissive = Vec3::new(1., 1., 1.)
+            }
+            if material.base_color_texture.is_none() {
+                material.base_color_texture = texture.map(|texture| TextureBinding { texture, sampler: Sampler::default() })
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Map statement texture, options like `-bm 0.5` come before the file name
+fn bind(
+    textures: &mut HashMap<(PathBuf, ColorSpace), &'static Texture>,
+    dir: &Path,
+    args: &[&str],
+    color_space: ColorSpace
+) -> Result<Option<TextureBinding>> {
+    let Some(file) = args.last() else { return Ok(None) };
+    let file = dir.join(file.replace('\\', "/"));
+    let texture = match textures.get(&(file.clone(), color_space)) {
+        Some(&texture) => texture,
+        None => {
+            let texture = &*Box::leak(Box::new(Texture::load(&file, color_space)?));
+            textures.insert((file, color_space), texture);
+            texture
+        }
+    };
+    Ok(Some(TextureBinding { texture, sampler: Sampler::default() }))
+}
+
+fn floats<const N: usize>(args: &[&str], path: &Path, at: impl Fn() -> String) -> Result<[f32;N]> {
+    let mut res = [0.;N];
+    for (i, value) in res.iter_mut().enumerate() {
+        *value = args.get(i)
+            .and_then(|arg| arg.parse().ok())
+            .ok_or_else(|| Error::validation(format!("expected {N} numbers")).in_file(path).at(at()))?
+    }
+    Ok(res)
+}
+
+/// 1 based, or relative to the end when negative, empty references are `None`
+fn index(reference: Option<&str>, len: usize, path: &Path, at: impl Fn() -> String) -> Result<Option<usize>> {
+    let Some(reference) = reference.filter(|reference| !reference.is_empty()) else { return Ok(None) };
+    let i = reference.parse::<i64>().map_err(|_| Error::validation(format!("invalid index {reference}")).in_file(path).at(at()))?;
+    let resolved = if i < 0 { len as i64 + i } else { i - 1 };
+    if resolved < 0 || resolved >= len as i64 {
+        return Err(Error::validation(format!("index {i} out of range of {len} elements")).in_file(path).at(at()))
+    }
+    Ok(Some(resolved as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(reference: &str, len: usize) -> Result<Option<usize>> {
+        index(Some(reference), len, Path::new("test.obj"), || "line 1".to_string())
+    }
+
+    #[test]
+    fn one_based_indices() {
+        assert_eq!(resolve("1", 3).unwrap(), Some(0));
+        assert_eq!(resolve("3", 3).unwrap(), Some(2));
+        assert!(resolve("0", 3).is_err());
+        assert!(resolve("4", 3).is_err());
+    }
+
+    #[test]
+    fn negative_indices() {
+        assert_eq!(resolve("-1", 3).unwrap(), Some(2));
+        assert_eq!(resolve("-3", 3).unwrap(), Some(0));
+        assert!(resolve("-4", 3).is_err());
+    }
+
+    #[test]
+    fn missing_and_invalid_references() {
+        assert_eq!(resolve("", 3).unwrap(), None);
+        assert_eq!(index(None, 3, Path::new("test.obj"), || "line 1".to_string()).unwrap(), None);
+        assert!(resolve("a", 3).is_err());
+    }
+}
@@ -1,99 +1,334 @@
-use std::{path::Path, sync::{Mutex, atomic::AtomicU32}};
-use math::{Vec3, Vec2, Transform};
+use std::{path::{Path, PathBuf}, ops::Range, cell::RefCell, collections::HashMap, sync::Mutex};
+use math::{Vec2, Vec3, Quaternion, Transform};
+use gltf::mesh::Mode;
 
-use crate::{texture::Texture, gimap::GIMap};
+use crate::{texture::{Texture, Sampler, ColorSpace}, material::{Material, TextureBinding, mul}, gimap::GIMap, bounds::{Aabb, Sphere}, bvh::Bvh, mesh::{Mesh, Vertex}, error::{Error, Result}, hierarchy::Hierarchy, obj, ply, stl};
 
-#[derive(Clone, Copy)]
-pub struct Vertex {
-    pub position: Vec3,
-    pub normal: Vec3,
-    pub uv: Vec2
+/// Range of `Mesh::indices` sharing one of `Object::materials`
+#[derive(Clone)]
+pub struct Primitive {
+    pub triangles: Range<usize>,
+    pub material: usize
 }
 
-pub static ID: AtomicU32 = AtomicU32::new(0);
+// Lightmap texture size used when the object has no base color image
+const DEFAULT_TEXTURE_SIZE: f32 = 1024.;
 
+/// Geometry and materials shared by every instance placing it in the scene
+pub struct Model {
+    pub name: String,
+    pub mesh: Mesh,
+    pub primitives: Vec<Primitive>,
+    pub materials: Vec<Material>,
+    /// Lightmap side in texels for uvs generated by `Mesh::unwrap_uvs`, which can't map a base color image
+    pub lightmap_size: Option<usize>,
+    /// Object space bounds of the triangles
+    pub bounds: Aabb,
+    pub bounding_sphere: Sphere,
+    /// Object space, over `Mesh::indices`
+    pub bvh: Bvh
+}
+impl Model {
+    pub fn new(name: impl Into<String>, mesh: Mesh, primitives: Vec<Primitive>, materials: Vec<Material>) -> Self {
+        let bounds = Aabb::from_points(mesh.vertices.iter().map(|vertex| vertex.position));
+        let bounding_sphere = Sphere::from_points(mesh.vertices.iter().map(|vertex| vertex.position));
+        let triangles = mesh.indices.iter().map(|triangle| triangle.map(|i| mesh.vertices[i as usize].position)).collect::<Vec<_>>();
+        let bvh = Bvh::new(&triangles);
+        Self { name: name.into(), mesh, primitives, materials, lightmap_size: None, bounds, bounding_sphere, bvh }
+    }
+    /// Single primitive model with a default material, `texture` as its base color
+    pub fn from_mesh(name: impl Into<String>, mesh: Mesh, texture: Option<&'static Texture>, lightmap_size: Option<usize>) -> Self {
+        let primitives = vec![Primitive { triangles: 0..mesh.indices.len(), material: 0 }];
+        let material = Material {
+            base_color_texture: texture.map(|texture| TextureBinding { texture, sampler: Sampler::default() }),
+            ..Default::default()
+        };
+        Self { lightmap_size, ..Self::new(name, mesh, primitives, vec![material]) }
+    }
+    /// Loads every mesh of the file merged into a single model, glTF nodes are ignored.
+    /// `.obj`, `.ply` and `.stl` files go through their own loader, anything else is read as glTF
+    pub fn load(path: impl AsRef<Path>, texture: Option<&'static Texture>) -> Result<Self> {
+        let path = path.as_ref();
+        if has_extension(path, "obj") { return obj::load(path, texture) }
+        if has_extension(path, "ply") { return ply::load(path, texture) }
+        if has_extension(path, "stl") { return stl::load(path, texture) }
+        let file = GltfFile::import(path, texture)?;
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut primitives = Vec::new();
+        for mesh in file.document.meshes() {
+            file.read_mesh(&mesh, None, &mut vertices, &mut indices, &mut primitives)?
+        }
+        if indices.is_empty() {
+            return Err(Error::validation("mesh has no triangles").in_file(&file.path))
+        }
+        let name = file.path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+        Ok(Self::new(name, Mesh::new(vertices, indices).with_adjacency(), primitives, file.materials))
+    }
+    #[inline(always)]
+    pub fn material(&self, triangle_id: usize) -> &Material {
+        let primitive = self.primitives.partition_point(|primitive| primitive.triangles.end <= triangle_id);
+        &self.materials[self.primitives[primitive].material]
+    }
+}
+
+/// Instance of a model, only the transform, material override and lightmap are its own, its name is on its hierarchy node
 pub struct Object {
-    pub id: u32,
-    pub triangles: Vec<[Vertex;3]>,
+    pub model: &'static Model,
+    /// Replaces every material of the model
+    pub material_override: Option<Material>,
     pub transform: Mutex<Transform>,
-    pub texture: &'static Texture,
     pub gimap: GIMap
 }
 impl Object {
-    pub fn load(
-        path: impl AsRef<Path>,
-        texture: &'static Texture,
-        transform: Transform
+    pub fn new(
+        model: &'static Model,
+        material_override: Option<Material>,
+        transform: Transform,
+        gi_texture_scale: f32
     ) -> Self {
-        let id = ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-        
-        let (gltf, buffers, _) = gltf::import(path).unwrap();
-        let meshes = gltf.meshes().collect::<Vec<_>>();
-        let primitives = meshes.iter().map(|mesh| mesh.primitives() ).flatten().collect::<Vec<_>>();
-        let readers = primitives.iter()
-            .map(|primitive| primitive.reader(|buffer| Some(&buffers[buffer.index()])) )
-            .collect::<Vec<_>>();
-        let mut readers_sizes = Vec::new();
+        let gimap = match model.lightmap_size {
+            Some(size) => GIMap::new(Vec2::new(size as f32, size as f32), &model.mesh, 1.),
+            None => {
+                let texture_size = material_override.iter()
+                    .chain(model.materials.iter())
+                    .find_map(|material| material.base_color_texture)
+                    .map_or(Vec2::new(DEFAULT_TEXTURE_SIZE, DEFAULT_TEXTURE_SIZE), |binding| binding.texture.size);
+                GIMap::new(texture_size, &model.mesh, gi_texture_scale)
+            }
+        };
+        Self {
+            model,
+            material_override,
+            gimap,
+            transform: transform.into()
+        }
+    }
+    /// Appends the default scene of the file (or its first one) to `objects`, one object per node with a mesh.
+    /// Every node is added to `hierarchy` under `parent`, named after the glTF node, so the objects follow their ancestors.
+    /// Nodes using the same mesh are instances of a single model, meshes without triangles are skipped
+    pub fn load_scene(
+        path: impl AsRef<Path>,
+        texture: Option<&'static Texture>,
+        hierarchy: &mut Hierarchy,
+        parent: Option<usize>,
+        gi_texture_scale: f32,
+        objects: &mut Vec<Self>
+    ) -> Result<()> {
+        let file = GltfFile::import(path.as_ref(), texture)?;
+        let scene = file.document.default_scene()
+            .or_else(|| file.document.scenes().next())
+            .ok_or_else(|| Error::validation("no scene to load").in_file(&file.path))?;
+        let mut models = HashMap::new();
+        let mut stack = scene.nodes().map(|node| (node, parent)).collect::<Vec<_>>();
+        stack.reverse();
+        while let Some((node, parent)) = stack.pop() {
+            let name = node.name().map_or_else(|| format!("node {}", node.index()), String::from);
+            let local = node_transform(&node);
+            if hierarchy.shears(parent, &local) {
+                return Err(Error::unsupported("rotation under a non uniformly scaled parent").in_file(&file.path).at(format!("node {}", node.index())))
+            }
+            let id = hierarchy.add(name, local, parent, None);
+            if let Some(mesh) = node.mesh() {
+                let model = match models.get(&mesh.index()) {
+                    Some(&model) => model,
+                    None => {
+                        let (mut vertices, mut indices, mut primitives) = (Vec::new(), Vec::new(), Vec::new());
+                        file.read_mesh(&mesh, Some(node.index()), &mut vertices, &mut indices, &mut primitives)?;
+                        let model = if indices.is_empty() {
+                            let error = Error::validation("mesh has no triangles").in_file(&file.path);
+                            eprintln!("warning: {}, skipped", error.at(format!("node {} mesh {}", node.index(), mesh.index())));
+                            None
+                        } else {
+                            let model = Model::new(
+                                mesh.name().map_or_else(|| format!("mesh {}", mesh.index()), String::from),
+                                Mesh::new(vertices, indices).with_adjacency(),
+                                primitives,
+                                file.materials.clone()
+                            );
+                            Some(&*Box::leak(Box::new(model)))
+                        };
+                        models.insert(mesh.index(), model);
+                        model
+                    }
+                };
+                if let Some(model) = model {
+                    hierarchy.nodes[id].object = Some(objects.len());
+                    objects.push(Self::new(model, None, hierarchy.nodes[id].world.clone(), gi_texture_scale))
+                }
+            }
+            let children = node.children().collect::<Vec<_>>();
+            stack.extend(children.into_iter().rev().map(|child| (child, Some(id))))
+        }
+        Ok(())
+    }
+    #[inline(always)]
+    pub fn material(&self, triangle_id: usize) -> &Material {
+        self.material_override.as_ref().unwrap_or_else(|| self.model.material(triangle_id))
+    }
+    /// Entry of `Model::materials`, or the override
+    #[inline(always)]
+    pub fn material_at(&self, index: usize) -> &Material {
+        self.material_override.as_ref().unwrap_or(&self.model.materials[index])
+    }
+}
 
-        let positions = readers.iter()
-            .map(|reader| {
-                let pos = reader.read_positions().unwrap().collect::<Vec<_>>();
-                readers_sizes.push(pos.len()as u32);
-                pos
-            })
-            .flatten()
-            .collect::<Vec<_>>();
+pub fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension().map_or(false, |ext| ext.eq_ignore_ascii_case(extension))
+}
+
+/// Local transform of a glTF node, matrices are decomposed into translation, rotation and scale
+pub fn node_transform(node: &gltf::Node) -> Transform {
+    let (translation, [x, y, z, w], scale) = node.transform().decomposed();
+    Transform {
+        translation: translation.into(),
+        rotation: Quaternion { x, y, z, w },
+        scale: scale.into()
+    }
+}
+/// `child` placed in the space of `parent`, exact unless it `shears`
+pub fn compose(parent: &Transform, child: &Transform) -> Transform {
+    Transform {
+        translation: parent.translation + parent.rotation * mul(parent.scale, child.translation),
+        rotation: parent.rotation * child.rotation,
+        scale: mul(parent.scale, child.scale)
+    }
+}
+
+/// Whether `child` placed in the space of `parent` needs a shear, which a `Transform` can't hold:
+/// the child rotation has to map every axis onto axes the parent scales by the same amount
+pub fn shears(parent: &Transform, child: &Transform) -> bool {
+    let s = parent.scale;
+    let tolerance = 1e-4 * s.x.abs().max(s.y.abs()).max(s.z.abs());
+    [(1., 0., 0., s.x), (0., 1., 0., s.y), (0., 0., 1., s.z)].into_iter().any(|(x, y, z, scale)| {
+        let axis = child.rotation * Vec3::new(x, y, z);
+        ((s.x - scale) * axis.x).abs() > tolerance || ((s.y - scale) * axis.y).abs() > tolerance || ((s.z - scale) * axis.z).abs() > tolerance
+    })
+}
 
-        let normals = readers.iter()
-            .map(|reader| reader.read_normals().unwrap().collect::<Vec<_>>())
-            .flatten()
-            .collect::<Vec<_>>();
+/// glTF document with its buffers and materials loaded
+struct GltfFile {
+    path: PathBuf,
+    document: gltf::Document,
+    buffers: Vec<gltf::buffer::Data>,
+    /// One per glTF material, plus a default one last for primitives without a material
+    materials: Vec<Material>
+}
+impl GltfFile {
+    /// `texture` stands in for missing base color images
+    fn import(path: &Path, texture: Option<&'static Texture>) -> Result<Self> {
+        let (document, buffers, images) = gltf::import(path).map_err(|error| Error::from(error).in_file(path))?;
 
-        let uvs = readers.iter()
-            .map(|reader| reader.read_tex_coords(0).unwrap().into_f32() )
-            .flatten()
-            .collect::<Vec<_>>();
+        // Images are decoded once per color space they are sampled in
+        let textures = RefCell::new(HashMap::new());
+        let resolve = |texture: &gltf::Texture, color_space: ColorSpace| -> Result<Option<&'static Texture>> {
+            let index = texture.source().index();
+            let Some(data) = images.get(index) else { return Ok(None) };
+            if let Some(&texture) = textures.borrow().get(&(index, color_space)) { return Ok(Some(texture)) }
+            let texture = Texture::from_gltf(data, color_space)
+                .map_err(|error| error.in_file(path).at(format!("image {index}")))?;
+            let texture = &*Box::leak(Box::new(texture));
+            textures.borrow_mut().insert((index, color_space), texture);
+            Ok(Some(texture))
+        };
 
-        let mut index_reader_offset = 0;
-        let triangles = readers.iter()
-            .zip(readers_sizes)
-            .map(|(reader, reader_size)| {
-                let res = reader.read_indices().unwrap().into_u32()
-                    .map(|i| i + index_reader_offset)
-                    .collect::<Vec<_>>();
-                index_reader_offset += reader_size;
-                res
+        let mut materials = document.materials()
+            .map(|material| {
+                let mut res = Material::from_gltf(&material, &resolve)?;
+                if res.base_color_texture.is_none() {
+                    res.base_color_texture = texture.map(|texture| TextureBinding {
+                        texture,
+                        sampler: material.pbr_metallic_roughness().base_color_texture()
+                            .map(|info| Sampler::from_gltf(&info.texture().sampler()))
+                            .unwrap_or_default()
+                    })
+                }
+                Ok(res)
             })
-            .flatten()
-            .collect::<Vec<_>>()
-            .chunks_exact(3)
-            .map(|v| [
-                Vertex {
-                    position: positions[v[0]as usize].into(),
-                    normal: normals[v[0]as usize].into(),
-                    uv: uvs[v[0]as usize].into()
-                },
-                Vertex {
-                    position: positions[v[1]as usize].into(),
-                    normal: normals[v[1]as usize].into(),
-                    uv: uvs[v[1]as usize].into()
-                },
-                Vertex {
-                    position: positions[v[2]as usize].into(),
-                    normal: normals[v[2]as usize].into(),
-                    uv: uvs[v[2]as usize].into()
+            .collect::<Result<Vec<_>>>()?;
+        materials.push(Material {
+            base_color_texture: texture.map(|texture| TextureBinding { texture, sampler: Sampler::default() }),
+            ..Default::default()
+        });
+
+        Ok(Self { path: path.to_path_buf(), document, buffers, materials })
+    }
+    /// Appends the triangles of the mesh primitives, `node` only shows up in error messages
+    fn read_mesh(
+        &self,
+        mesh: &gltf::Mesh,
+        node: Option<usize>,
+        vertices: &mut Vec<Vertex>,
+        indices: &mut Vec<[u32;3]>,
+        primitives: &mut Vec<Primitive>
+    ) -> Result<()> {
+        let path = self.path.as_path();
+        for primitive in mesh.primitives() {
+            let reader = primitive.reader(|buffer| Some(&self.buffers[buffer.index()]));
+            let location = || match node {
+                Some(node) => format!("node {node} mesh {} primitive {}", mesh.index(), primitive.index()),
+                None => format!("mesh {} primitive {}", mesh.index(), primitive.index())
+            };
+            let Some(positions) = reader.read_positions() else {
+                return Err(Error::validation("missing POSITION attribute").in_file(path).at(location()))
+            };
+            let positions = positions.collect::<Vec<_>>();
+            let sequence = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+                None => (0..positions.len() as u32).collect()
+            };
+            let triangles = match primitive.mode() {
+                Mode::Triangles => sequence.chunks_exact(3).map(|v| [v[0], v[1], v[2]]).collect::<Vec<_>>(),
+                Mode::TriangleStrip => (0..sequence.len().saturating_sub(2))
+                    .map(|i| [sequence[i], sequence[i + 1 + i % 2], sequence[i + 2 - i % 2]])
+                    .collect(),
+                Mode::TriangleFan => (0..sequence.len().saturating_sub(2))
+                    .map(|i| [sequence[i + 1], sequence[i + 2], sequence[0]])
+                    .collect(),
+                mode => {
+                    eprintln!("warning: {}, skipped", Error::unsupported(format!("{mode:?} primitive")).in_file(path).at(location()));
+                    continue
                 }
-            ])
-            .collect::<Vec<_>>();
-            
-        let gimap = GIMap::new(id, &texture, &triangles, 1. / 10.).into();
-            
-        Self {
-            id,
-            triangles,
-            texture,
-            gimap,
-            transform: transform.into()
+            };
+            if let Some(i) = triangles.iter().flatten().find(|&&i| i as usize >= positions.len()) {
+                return Err(Error::validation(format!("index {i} out of range of {} vertices", positions.len())).in_file(path).at(location()))
+            }
+
+            // Attributes are regenerated when missing or not matching the positions
+            let normals = reader.read_normals()
+                .map(|normals| normals.collect::<Vec<_>>())
+                .filter(|normals| normals.len() == positions.len());
+            let uvs = reader.read_tex_coords(0)
+                .map(|uvs| uvs.into_f32().collect::<Vec<_>>())
+                .filter(|uvs| uvs.len() == positions.len());
+            let colors = reader.read_colors(0)
+                .map(|colors| colors.into_rgb_f32().collect::<Vec<_>>())
+                .filter(|colors| colors.len() == positions.len());
+            let mut part = Mesh {
+                vertices: positions.iter()
+                    .enumerate()
+                    .map(|(i, &position)| Vertex {
+                        position: position.into(),
+                        normal: normals.as_ref().map_or(Vec3::default(), |normals| normals[i].into()),
+                        uv: uvs.as_ref().map_or(Vec2::default(), |uvs| uvs[i].into()),
+                        color: colors.as_ref().map_or(Vec3::new(1., 1., 1.), |colors| colors[i].into())
+                    })
+                    .collect(),
+                indices: triangles,
+                adjacency: None
+            };
+            // Vertices of non indexed primitives belong to a single triangle, so they end up flat shaded
+            if normals.is_none() { part.smooth_normals() }
+            if uvs.is_none() { part.project_uvs() }
+
+            let offset = vertices.len() as u32;
+            primitives.push(Primitive {
+                triangles: indices.len()..indices.len() + part.indices.len(),
+                material: primitive.material().index().unwrap_or(self.materials.len() - 1)
+            });
+            indices.extend(part.indices.iter().map(|triangle| triangle.map(|i| i + offset)));
+            vertices.extend(part.vertices)
         }
+        Ok(())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,339 @@
+use std::{path::Path, str::SplitAsciiWhitespace};
+use math::{Vec2, Vec3};
+
+use crate::{
+    object::Model,
+    mesh::{Mesh, Vertex, triangulate},
+    texture::{Texture, srgb_to_linear},
+    error::{Error, Result}
+};
+
+#[derive(Clone, Copy)]
+enum Type { I8, U8, I16, U16, I32, U32, F32, F64 }
+impl Type {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "char" | "int8" => Type::I8,
+            "uchar" | "uint8" => Type::U8,
+            "short" | "int16" => Type::I16,
+            "ushort" | "uint16" => Type::U16,
+            "int" | "int32" => Type::I32,
+            "uint" | "uint32" => Type::U32,
+            "float" | "float32" => Type::F32,
+            "double" | "float64" => Type::F64,
+            _ => return None
+        })
+    }
+    fn size(self) -> usize {
+        match self {
+            Type::I8 | Type::U8 => 1,
+            Type::I16 | Type::U16 => 2,
+            Type::I32 | Type::U32 | Type::F32 => 4,
+            Type::F64 => 8
+        }
+    }
+    fn is_float(self) -> bool {
+        matches!(self, Type::F32 | Type::F64)
+    }
+    /// Value of a full color channel
+    fn max(self) -> f64 {
+        match self {
+            Type::I8 => i8::MAX as f64,
+            Type::U8 => u8::MAX as f64,
+            Type::I16 => i16::MAX as f64,
+            Type::U16 => u16::MAX as f64,
+            Type::I32 => i32::MAX as f64,
+            Type::U32 => u32::MAX as f64,
+            Type::F32 | Type::F64 => 1.
+        }
+    }
+}
+
+struct Property {
+    name: String,
+    ty: Type,
+    /// Type of the item count of list properties
+    count: Option<Type>
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>
+}
+impl Element {
+    fn find(&self, names: &[&str]) -> Option<usize> {
+        self.properties.iter().position(|property| names.contains(&property.name.as_str()))
+    }
+}
+
+/// Data following the header
+enum Body<'a> {
+    Ascii(SplitAsciiWhitespace<'a>),
+    Binary { data: &'a [u8], offset: usize, big_endian: bool }
+}
+impl Body<'_> {
+    fn read(&mut self, ty: Type) -> Option<f64> {
+        match self {
+            Body::Ascii(tokens) => tokens.next()?.parse().ok(),
+            Body::Binary { data, offset, big_endian } => {
+                let size = ty.size();
+                let mut b = [0;8];
+                b[..size].copy_from_slice(data.get(*offset..*offset + size)?);
+                *offset += size;
+                if *big_endian { b[..size].reverse() }
+                Some(match ty {
+                    Type::I8 => b[0] as i8 as f64,
+                    Type::U8 => b[0] as f64,
+                    Type::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                    Type::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                    Type::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    Type::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    Type::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    Type::F64 => f64::from_le_bytes(b)
+                })
+            }
+        }
+    }
+}
+
+/// Loads the `vertex` and `face` elements of an ASCII or binary PLY file as a single model.
+/// Normals are smoothed when the vertices have none, uvs are generated for the lightmap when missing,
+/// `texture` needs the file ones. Integer vertex colors are taken as sRGB, float ones as linear
+pub fn load(path: &Path, texture: Option<&'static Texture>) -> Result<Model> {
+    let data = std::fs::read(path).map_err(|error| Error::from(error).in_file(path))?;
+    let Ply { vertices, triangles, has_normals, has_uvs } = parse(&data, path)?;
+    if texture.is_some() && !has_uvs {
+        return Err(Error::unsupported("base color texture on a mesh without uvs").in_file(path))
+    }
+    let mut mesh = Mesh::new(vertices, triangles);
+    if !has_normals { mesh.smooth_normals() }
+    let lightmap_size = (!has_uvs).then(|| mesh.unwrap_uvs());
+    let name = path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+    Ok(Model::from_mesh(name, mesh.with_adjacency(), texture, lightmap_size))
+}
+
+/// Vertices and triangulated faces of a PLY file
+struct Ply {
+    vertices: Vec<Vertex>,
+    triangles: Vec<[u32;3]>,
+    has_normals: bool,
+    has_uvs: bool
+}
+
+/// `path` only shows up in error messages
+fn parse(data: &[u8], path: &Path) -> Result<Ply> {
+    let end = data.windows(10)
+        .position(|window| window == b"end_header")
+        .ok_or_else(|| Error::decode("missing end_header").in_file(path))?;
+    let start = data[end..].iter().position(|&byte| byte == b'\n').map_or(data.len(), |i| end + i + 1);
+    let header = std::str::from_utf8(&data[..end]).map_err(|_| Error::decode("header is not text").in_file(path))?;
+
+    let mut lines = header.lines();
+    if lines.next().map(str::trim) != Some("ply") {
+        return Err(Error::decode("not a PLY file").in_file(path))
+    }
+    let mut format = None;
+    let mut elements = Vec::<Element>::new();
+    for (line, text) in lines.enumerate() {
+        let at = || format!("header line {}", line + 2);
+        let tokens = text.split_whitespace().collect::<Vec<_>>();
+        let ty = |name: &str| Type::parse(name)
+            .ok_or_else(|| Error::unsupported(format!("property type {name}")).in_file(path).at(at()));
+        match tokens[..] {
+            ["format", name, _] => format = Some(match name {
+                "ascii" => None,
+                "binary_little_endian" => Some(false),
+                "binary_big_endian" => Some(true),
+                _ => return Err(Error::unsupported(format!("{name} format")).in_file(path).at(at()))
+            }),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| Error::validation(format!("invalid count {count}")).in_file(path).at(at()))?,
+                properties: Vec::new()
+            }),
+            ["property", "list", count, item, name] => if let Some(element) = elements.last_mut() {
+                element.properties.push(Property { name: name.to_string(), ty: ty(item)?, count: Some(ty(count)?) })
+            },
+            ["property", item, name] => if let Some(element) = elements.last_mut() {
+                element.properties.push(Property { name: name.to_string(), ty: ty(item)?, count: None })
+            },
+            _ => {}
+        }
+    }
+    let mut body = match format {
+        Some(None) => Body::Ascii(std::str::from_utf8(&data[start..])
+            .map_err(|_| Error::decode("ASCII data is not text").in_file(path))?
+            .split_ascii_whitespace()),
+        Some(Some(big_endian)) => Body::Binary { data: &data[start..], offset: 0, big_endian },
+        None => return Err(Error::decode("missing format").in_file(path))
+    };
+
+    let mut vertices = Vec::new();
+    let mut polygons = Vec::new();
+    let (mut has_normals, mut has_uvs) = (false, false);
+    for element in elements.iter() {
+        let position = ["x", "y", "z"].map(|name| element.find(&[name]));
+        let normal = ["nx", "ny", "nz"].map(|name| element.find(&[name]));
+        let color = [["red", "r", "diffuse_red"], ["green", "g", "diffuse_green"], ["blue", "b", "diffuse_blue"]].map(|names| element.find(&names));
+        let uv = [["s", "u", "texture_u"], ["t", "v", "texture_v"]].map(|names| element.find(&names));
+        let indices = element.find(&["vertex_indices", "vertex_index"]);
+        has_normals |= element.name == "vertex" && normal.iter().all(Option::is_some);
+        has_uvs |= element.name == "vertex" && uv.iter().all(Option::is_some);
+
+        let mut values = vec![0.;element.properties.len()];
+        let mut list = Vec::new();
+        for i in 0..element.count {
+            let truncated = || Error::validation("truncated or invalid data").in_file(path).at(format!("{} {i}", element.name));
+            for (p, property) in element.properties.iter().enumerate() {
+                match property.count {
+                    Some(count) => {
+                        let count = body.read(count).ok_or_else(truncated)? as usize;
+                        if Some(p) == indices { list.clear() }
+                        for _ in 0..count {
+                            let value = body.read(property.ty).ok_or_else(truncated)?;
+                            if Some(p) == indices { list.push(value as i64) }
+                        }
+                    },
+                    None => values[p] = body.read(property.ty).ok_or_else(truncated)?
+                }
+            }
+            match element.name.as_str() {
+                "vertex" => {
+                    let [Some(x), Some(y), Some(z)] = position else {
+                        return Err(Error::validation("vertex without x y z").in_file(path))
+                    };
+                    let channel = |c: Option<usize>| c.map_or(1., |c| {
+                        let ty = element.properties[c].ty;
+                        let value = (values[c] / ty.max()) as f32;
+                        if ty.is_float() { value } else { srgb_to_linear(value) }
+                    });
+                    vertices.push(Vertex {
+                        position: Vec3::new(values[x] as f32, values[y] as f32, values[z] as f32),
+                        normal: match normal {
+                            [Some(x), Some(y), Some(z)] => Vec3::new(values[x] as f32, values[y] as f32, values[z] as f32),
+                            _ => Vec3::default()
+                        },
+                        // Images start at the top
+                        uv: match uv {
+                            [Some(u), Some(v)] => Vec2::new(values[u] as f32, 1. - values[v] as f32),
+                            _ => Vec2::default()
+                        },
+                        color: Vec3::new(channel(color[0]), channel(color[1]), channel(color[2]))
+                    })
+                },
+                "face" if indices.is_some() => polygons.push(std::mem::take(&mut list)),
+                _ => {}
+            }
+        }
+    }
+
+    let mut triangles = Vec::new();
+    for (i, polygon) in polygons.iter().enumerate() {
+        if let Some(index) = polygon.iter().find(|&&index| index < 0 || index as usize >= vertices.len()) {
+            return Err(Error::validation(format!("index {index} out of range of {} vertices", vertices.len())).in_file(path).at(format!("face {i}")))
+        }
+        if polygon.len() < 3 { continue }
+        let points = polygon.iter().map(|&index| vertices[index as usize].position).collect::<Vec<_>>();
+        triangles.extend(triangulate(&points).into_iter().map(|triangle| triangle.map(|k| polygon[k] as u32)))
+    }
+    if triangles.is_empty() {
+        return Err(Error::unsupported("point clouds, the file has no faces").in_file(path))
+    }
+    Ok(Ply { vertices, triangles, has_normals, has_uvs })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "element vertex 3
+property float x
+property float y
+property float z
+element face 1
+property list uchar int vertex_indices
+end_header
+";
+
+    fn xyz(v: Vec3) -> [f32;3] {
+        [v.x, v.y, v.z]
+    }
+
+    fn path() -> &'static Path {
+        Path::new("test.ply")
+    }
+
+    /// Triangle with the face indices given
+    fn binary(big_endian: bool, indices: [i32;3]) -> Vec<u8> {
+        let format = if big_endian { "binary_big_endian" } else { "binary_little_endian" };
+        let mut data = format!("ply\nformat {format} 1.0\n{HEADER}").into_bytes();
+        for value in [0f32, 0., 0., 1., 0., 0., 0., 2., 0.] {
+            data.extend(if big_endian { value.to_be_bytes() } else { value.to_le_bytes() })
+        }
+        data.push(3);
+        for index in indices {
+            data.extend(if big_endian { index.to_be_bytes() } else { index.to_le_bytes() })
+        }
+        data
+    }
+
+    #[test]
+    fn reads_both_endiannesses() {
+        for big_endian in [false, true] {
+            let ply = parse(&binary(big_endian, [0, 1, 2]), path()).unwrap();
+            assert_eq!(ply.vertices.len(), 3);
+            assert_eq!(xyz(ply.vertices[2].position), [0., 2., 0.]);
+            assert_eq!(ply.triangles, vec![[0, 1, 2]]);
+            assert!(!ply.has_normals && !ply.has_uvs)
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let data = binary(true, [0, 1, 2]);
+        assert!(parse(&data[..data.len() - 2], path()).is_err());
+        let text = "ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\nproperty float y\nproperty float z\nend_header\n0 0 0\n1 0\n";
+        assert!(parse(text.as_bytes(), path()).is_err());
+    }
+
+    #[test]
+    fn rejects_out_of_range_indices() {
+        assert!(parse(&binary(false, [0, 1, 3]), path()).is_err());
+        assert!(parse(&binary(false, [0, -1, 2]), path()).is_err());
+    }
+
+    #[test]
+    fn triangulates_ascii_quads_with_colors() {
+        let text = "ply
+format ascii 1.0
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 255 0 0
+1 0 0 255 0 0
+1 1 0 0 255 0
+0 1 0 0 0 255
+4 0 1 2 3
+";
+        let ply = parse(text.as_bytes(), path()).unwrap();
+        assert_eq!(ply.triangles.len(), 2);
+        assert_eq!(xyz(ply.vertices[0].color), [1., 0., 0.]);
+        assert_eq!(xyz(ply.vertices[3].color), [0., 0., 1.]);
+    }
+
+    #[test]
+    fn keeps_float_colors_linear() {
+        let text = format!("ply\nformat ascii 1.0\n{}", HEADER.replace("property float z\n", "property float z\nproperty float red\nproperty float green\nproperty float blue\n"))
+            + "0 0 0 0.5 0.5 0.5\n1 0 0 0.5 0.5 0.5\n0 1 0 0.5 0.5 0.5\n3 0 1 2\n";
+        let ply = parse(text.as_bytes(), path()).unwrap();
+        assert_eq!(xyz(ply.vertices[0].color), [0.5, 0.5, 0.5]);
+    }
+}
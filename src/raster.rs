@@ -0,0 +1,276 @@
+use math::{Vec2, Vec3};
+
+pub const SUBPIXEL_BITS: i64 = 8;
+pub const SUBPIXEL: i64 = 1 << SUBPIXEL_BITS;
+const HALF_PIXEL: i64 = SUBPIXEL / 2;
+// Keeps fixed point edge products far away from i64 overflow
+const GUARD_BAND: f32 = (1 << 14) as f32;
+
+#[inline(always)]
+fn to_fixed(v: f32) -> i64 {
+    (v.max(-GUARD_BAND).min(GUARD_BAND) * SUBPIXEL as f32).round() as i64
+}
+#[inline(always)]
+fn edge(ax: i64, ay: i64, bx: i64, by: i64, px: i64, py: i64) -> i64 {
+    (bx - ax) * (py - ay) - (by - ay) * (px - ax)
+}
+#[inline(always)]
+fn is_top_left(ax: i64, ay: i64, bx: i64, by: i64) -> bool {
+    let dx = bx - ax;
+    let dy = by - ay;
+    dy < 0 || (dy == 0 && dx > 0)
+}
+#[inline(always)]
+fn bias(ax: i64, ay: i64, bx: i64, by: i64) -> i64 {
+    if is_top_left(ax, ay, bx, by) { 0 } else { -1 }
+}
+
+struct Edges {
+    minx: i64,
+    maxx: i64,
+    dx: [i64;3],
+    bias: [i64;3],
+    inv_area: f32,
+    flipped: bool
+}
+
+/// Rasterizes the triangle `a b c` (pixel units) inside the rect `[x0, x1) x [y0, y1)`,
+/// sampling at pixel centers with 1/`SUBPIXEL` vertex precision and a top-left fill rule,
+/// so triangles sharing an edge never cover the same pixel twice nor leave gaps.
+/// `fragment` receives the pixel and the barycentric weights of `a`, `b` and `c`.
+#[inline(always)]
+pub fn raster_triangle(
+    x0: i32, y0: i32, x1: i32, y1: i32,
+    a: Vec2, b: Vec2, c: Vec2,
+    fragment: impl FnMut(i32, i32, Vec3)
+) {
+    #[cfg(target_arch = "x86_64")]
+    let avx2 = is_x86_feature_detected!("avx2");
+    #[cfg(not(target_arch = "x86_64"))]
+    let avx2 = false;
+    raster_triangle_with(avx2, x0, y0, x1, y1, a, b, c, fragment)
+}
+
+/// `raster_triangle` with the row loop chosen by the caller, `avx2` is only set when the CPU supports it
+#[inline(always)]
+fn raster_triangle_with(
+    avx2: bool,
+    x0: i32, y0: i32, x1: i32, y1: i32,
+    a: Vec2, b: Vec2, c: Vec2,
+    mut fragment: impl FnMut(i32, i32, Vec3)
+) {
+    let (ax, ay) = (to_fixed(a.x), to_fixed(a.y));
+    let (mut bx, mut by) = (to_fixed(b.x), to_fixed(b.y));
+    let (mut cx, mut cy) = (to_fixed(c.x), to_fixed(c.y));
+
+    let mut area = edge(ax, ay, bx, by, cx, cy);
+    if area == 0 { return }
+    let flipped = area < 0;
+    if flipped {
+        std::mem::swap(&mut bx, &mut cx);
+        std::mem::swap(&mut by, &mut cy);
+        area = -area
+    }
+
+    let minx = ((ax.min(bx).min(cx) - HALF_PIXEL) >> SUBPIXEL_BITS).max(x0 as i64);
+    let miny = ((ay.min(by).min(cy) - HALF_PIXEL) >> SUBPIXEL_BITS).max(y0 as i64);
+    let maxx = ((ax.max(bx).max(cx) - HALF_PIXEL) >> SUBPIXEL_BITS).min(x1 as i64 - 1);
+    let maxy = ((ay.max(by).max(cy) - HALF_PIXEL) >> SUBPIXEL_BITS).min(y1 as i64 - 1);
+    if minx > maxx || miny > maxy { return }
+
+    let edges = Edges {
+        minx, maxx,
+        dx: [(by - cy) * SUBPIXEL, (cy - ay) * SUBPIXEL, (ay - by) * SUBPIXEL],
+        bias: [bias(bx, by, cx, cy), bias(cx, cy, ax, ay), bias(ax, ay, bx, by)],
+        inv_area: 1. / area as f32,
+        flipped
+    };
+    let dy = [(cx - bx) * SUBPIXEL, (ax - cx) * SUBPIXEL, (bx - ax) * SUBPIXEL];
+
+    let px = (minx << SUBPIXEL_BITS) + HALF_PIXEL;
+    let py = (miny << SUBPIXEL_BITS) + HALF_PIXEL;
+    let mut w = [
+        edge(bx, by, cx, cy, px, py) + edges.bias[0],
+        edge(cx, cy, ax, ay, px, py) + edges.bias[1],
+        edge(ax, ay, bx, by, px, py) + edges.bias[2]
+    ];
+
+    #[cfg(not(target_arch = "x86_64"))]
+    let _ = avx2;
+    for y in miny..=maxy {
+        #[cfg(target_arch = "x86_64")]
+        if avx2 {
+            unsafe { raster_row_avx2(&edges, y, w, &mut fragment) }
+        } else {
+            raster_row(&edges, y, w, &mut fragment)
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        raster_row(&edges, y, w, &mut fragment);
+        w[0] += dy[0];
+        w[1] += dy[1];
+        w[2] += dy[2];
+    }
+}
+
+/// Emits the fragments of a block of 4 pixels starting at `x`, `mask` holds one bit per covered pixel
+#[inline(always)]
+fn emit(
+    edges: &Edges,
+    x: i64, y: i64,
+    mut mask: u32,
+    w: [i64;3],
+    fragment: &mut impl FnMut(i32, i32, Vec3)
+) {
+    if edges.maxx - x < 3 {
+        mask &= (1 << (edges.maxx - x + 1)) - 1
+    }
+    while mask != 0 {
+        let lane = mask.trailing_zeros() as i64;
+        mask &= mask - 1;
+        let wa = (w[0] + edges.dx[0] * lane - edges.bias[0]) as f32 * edges.inv_area;
+        let wb = (w[1] + edges.dx[1] * lane - edges.bias[1]) as f32 * edges.inv_area;
+        let wc = (w[2] + edges.dx[2] * lane - edges.bias[2]) as f32 * edges.inv_area;
+        fragment(
+            (x + lane) as i32, y as i32,
+            if edges.flipped { Vec3::new(wa, wc, wb) } else { Vec3::new(wa, wb, wc) }
+        )
+    }
+}
+
+#[inline(always)]
+fn raster_row(
+    edges: &Edges,
+    y: i64,
+    mut w: [i64;3],
+    fragment: &mut impl FnMut(i32, i32, Vec3)
+) {
+    let mut x = edges.minx;
+    while x <= edges.maxx {
+        let mut mask = 0;
+        let mut lane = w;
+        for i in 0..4 {
+            mask |= (((lane[0] | lane[1] | lane[2]) >= 0) as u32) << i;
+            lane[0] += edges.dx[0];
+            lane[1] += edges.dx[1];
+            lane[2] += edges.dx[2];
+        }
+        if mask != 0 { emit(edges, x, y, mask, w, fragment) }
+        w = lane;
+        x += 4
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn raster_row_avx2(
+    edges: &Edges,
+    y: i64,
+    mut w: [i64;3],
+    fragment: &mut impl FnMut(i32, i32, Vec3)
+) {
+    use std::arch::x86_64::*;
+    let [d0, d1, d2] = edges.dx;
+    let mut w0 = _mm256_set_epi64x(w[0] + d0 * 3, w[0] + d0 * 2, w[0] + d0, w[0]);
+    let mut w1 = _mm256_set_epi64x(w[1] + d1 * 3, w[1] + d1 * 2, w[1] + d1, w[1]);
+    let mut w2 = _mm256_set_epi64x(w[2] + d2 * 3, w[2] + d2 * 2, w[2] + d2, w[2]);
+    let step0 = _mm256_set1_epi64x(d0 * 4);
+    let step1 = _mm256_set1_epi64x(d1 * 4);
+    let step2 = _mm256_set1_epi64x(d2 * 4);
+    let mut x = edges.minx;
+    while x <= edges.maxx {
+        // The sign bit of each lane is set when the pixel is outside any of the edges
+        let outside = _mm256_or_si256(_mm256_or_si256(w0, w1), w2);
+        let mask = !(_mm256_movemask_pd(_mm256_castsi256_pd(outside)) as u32) & 0b1111;
+        if mask != 0 { emit(edges, x, y, mask, w, fragment) }
+        w0 = _mm256_add_epi64(w0, step0);
+        w1 = _mm256_add_epi64(w1, step1);
+        w2 = _mm256_add_epi64(w2, step2);
+        w[0] += d0 * 4;
+        w[1] += d1 * 4;
+        w[2] += d2 * 4;
+        x += 4
+    }
+}
+
+/// Rasterizes the segment `a b` (pixel units) inside the rect `[x0, x1) x [y0, y1)`, one pixel
+/// per step along the major axis. The steps are taken on the whole segment before clipping, so
+/// adjacent rects split the line without gaps or overlaps.
+/// `fragment` receives the pixel and the position along the segment, 0 at `a` and 1 at `b`.
+pub fn raster_line(
+    x0: i32, y0: i32, x1: i32, y1: i32,
+    a: Vec2, b: Vec2,
+    mut fragment: impl FnMut(i32, i32, f32)
+) {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    // Liang-Barsky clipping against the rect
+    let (mut t0, mut t1) = (0f32, 1f32);
+    for (p, q) in [
+        (-dx, a.x - x0 as f32), (dx, x1 as f32 - a.x),
+        (-dy, a.y - y0 as f32), (dy, y1 as f32 - a.y)
+    ] {
+        if p == 0. {
+            if q < 0. { return }
+            continue
+        }
+        let r = q / p;
+        if p < 0. { t0 = t0.max(r) } else { t1 = t1.min(r) }
+    }
+    if t0 > t1 { return }
+
+    let steps = dx.abs().max(dy.abs()).max(1.);
+    for i in (t0 * steps).ceil() as i32..=(t1 * steps).floor() as i32 {
+        let t = i as f32 / steps;
+        let x = (a.x + dx * t).floor() as i32;
+        let y = (a.y + dy * t).floor() as i32;
+        if x < x0 || x >= x1 || y < y0 || y >= y1 { continue }
+        fragment(x, y, t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: usize = 32;
+
+    /// Quad split into a grid of triangle pairs, the shared edges sit on sub-pixel offsets and
+    /// pixel centers, the diagonals alternate and every other triangle is wound the other way
+    fn coverage(avx2: bool) -> Vec<u32> {
+        let xs = [0.3, 3.5, 7.1, 7.999, 12.5, 17.26, 25.9];
+        let ys = [0.7, 4.5, 9.33, 15.5, 21.01];
+        let mut counts = vec![0;SIZE * SIZE];
+        let mut fragment = |x: i32, y: i32, _| counts[y as usize * SIZE + x as usize] += 1;
+        for j in 0..ys.len() - 1 {
+            for i in 0..xs.len() - 1 {
+                let [p00, p10, p01, p11] = [(i, j), (i + 1, j), (i, j + 1), (i + 1, j + 1)].map(|(i, j)| Vec2::new(xs[i], ys[j]));
+                let triangles = if (i + j) % 2 == 0 { [[p00, p10, p11], [p00, p01, p11]] } else { [[p00, p10, p01], [p10, p11, p01]] };
+                for [a, b, c] in triangles {
+                    raster_triangle_with(avx2, 0, 0, SIZE as i32, SIZE as i32, a, b, c, &mut fragment)
+                }
+            }
+        }
+        counts
+    }
+
+    fn assert_covered_once(counts: &[u32]) {
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let (cx, cy) = (x as f32 + 0.5, y as f32 + 0.5);
+                let inside = (0.3..25.9).contains(&cx) && (0.7..21.01).contains(&cy);
+                assert_eq!(counts[y * SIZE + x], inside as u32, "pixel {x} {y}")
+            }
+        }
+    }
+
+    #[test]
+    fn shared_edges_cover_pixels_once() {
+        assert_covered_once(&coverage(false))
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn shared_edges_cover_pixels_once_avx2() {
+        if !is_x86_feature_detected!("avx2") { return }
+        assert_covered_once(&coverage(true))
+    }
+}
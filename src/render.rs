@@ -1,167 +1,448 @@
-use math::{Vec4, Vec2, Vec3};
+use math::{Vec4, Vec2, Vec3, Transform};
 
-use crate::{object::Object, texture::Texture, camera::Camera, gimap::GIMap};
+use crate::{object::Object, camera::{Camera, NEAR, FAR}, dir_light::DirectionalLight, raster::{raster_triangle, raster_line}, material::{AlphaMode, mul}, texture::srgb_to_linear, debug_draw::Line};
+
+pub const TILE_SIZE: i32 = 64;
+// Lets lines lying on a surface win the depth test against it
+const LINE_DEPTH_BIAS: f32 = 1e-5;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ViewMode {
+    Shaded,
+    /// Baked GIMap value only, black until the first pass completes
+    Lighting,
+    Albedo,
+    /// World space shading normal, after normal mapping
+    Normals,
+    Uvs,
+    /// Checkerboard of the GIMap texels
+    TexelDensity,
+    /// Logarithmic view depth, white at the near plane
+    Depth,
+    TriangleIds,
+    ObjectIds,
+    /// Fragments shaded per pixel, from blue (1) to red (5 or more)
+    Overdraw
+}
+impl ViewMode {
+    pub fn next(self) -> Self {
+        match self {
+            ViewMode::Shaded => ViewMode::Lighting,
+            ViewMode::Lighting => ViewMode::Albedo,
+            ViewMode::Albedo => ViewMode::Normals,
+            ViewMode::Normals => ViewMode::Uvs,
+            ViewMode::Uvs => ViewMode::TexelDensity,
+            ViewMode::TexelDensity => ViewMode::Depth,
+            ViewMode::Depth => ViewMode::TriangleIds,
+            ViewMode::TriangleIds => ViewMode::ObjectIds,
+            ViewMode::ObjectIds => ViewMode::Overdraw,
+            ViewMode::Overdraw => ViewMode::Shaded
+        }
+    }
+}
+
+const HEATMAP: [[f32;3];6] = [
+    [0., 0., 0.],
+    [0., 0., 1.],
+    [0., 1., 1.],
+    [0., 1., 0.],
+    [1., 1., 0.],
+    [1., 0., 0.]
+];
+
+pub struct ScreenTriangle {
+    pub object: usize,
+    /// Index in `Mesh::indices`
+    pub triangle: usize,
+    pub material: usize,
+    pub blend: bool,
+    pub a: Vec2,
+    pub b: Vec2,
+    pub c: Vec2,
+    pub z: Vec3,
+    pub w: Vec3,
+    pub uvx: Vec3,
+    pub uvy: Vec3,
+    /// World space positions, normals and vertex colors divided by w
+    pub position: [Vec3;3],
+    pub normal: [Vec3;3],
+    pub color: [Vec3;3],
+    /// Screen space derivatives of the barycentric weights
+    pub dbdx: Vec3,
+    pub dbdy: Vec3
+}
+
+pub struct ScreenLine {
+    pub a: Vec2,
+    pub b: Vec2,
+    pub za: f32,
+    pub zb: f32,
+    pub color: Vec3
+}
+
+pub struct Tile {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    /// Linear HDR RGBA
+    pub pixels: Vec<f32>,
+    pub zbuffer: Vec<f32>,
+    /// Fragments rasterized per pixel, filled in the overdraw view only
+    pub overdraw: Vec<u32>,
+    pub triangles: Vec<u32>,
+    pub lines: Vec<u32>
+}
+impl Tile {
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
+        Self {
+            x, y,
+            width, height,
+            pixels: vec![0.;(width * height * 4)as usize],
+            zbuffer: vec![f32::MAX;(width * height)as usize],
+            overdraw: vec![0;(width * height)as usize],
+            triangles: Vec::new(),
+            lines: Vec::new()
+        }
+    }
+}
+
+pub fn create_tiles(width: i32, height: i32) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    for y in (0..height).step_by(TILE_SIZE as usize) {
+        for x in (0..width).step_by(TILE_SIZE as usize) {
+            tiles.push(Tile::new(x, y, TILE_SIZE.min(width - x), TILE_SIZE.min(height - y)))
+        }
+    }
+    tiles
+}
 
 #[inline(always)]
-pub fn clear(pixels: &mut [u8]) {
+pub fn clear(pixels: &mut [f32]) {
     let l = pixels.len();
     let mut i = 0;
     while i < l {
-        pixels[i  ] = 0;
-        pixels[i+1] = 0;
-        pixels[i+2] = 0;
-        pixels[i+3] = 255;
+        pixels[i  ] = 0.;
+        pixels[i+1] = 0.;
+        pixels[i+2] = 0.;
+        pixels[i+3] = 1.;
         i += 4;
     }
 }
+/// World and clip space vertex, computed once per frame and shared by the triangles using it
+#[derive(Clone, Copy)]
+pub struct TransformedVertex {
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub clip: Vec4
+}
+
+/// `vertices` is scratch space reused between objects
 #[inline(always)]
-pub fn draw(
+pub fn project(
     width: i32, height: i32,
-    pixels: &mut [u8],
-    zbuffer: &mut [f32],
+    object_id: usize,
     object: &Object,
-    camera: &Camera
+    transform: &Transform,
+    camera: &Camera,
+    vertices: &mut Vec<TransformedVertex>,
+    triangles: &mut Vec<ScreenTriangle>
 ) {
-    for [a, b, c] in object.triangles.iter() {
-        let transform = object.transform.lock().unwrap().clone();
+    vertices.clear();
+    vertices.extend(object.model.mesh.vertices.iter().map(|vertex| {
+        let position = transform.clone() * vertex.position;
+        TransformedVertex {
+            position,
+            normal: (transform.rotation * vertex.normal).normalized(),
+            clip: camera.mat * position.extend(1.)
+        }
+    }));
+    for primitive in object.model.primitives.iter() {
+        for (triangle_id, triangle) in object.model.mesh.indices[primitive.triangles.clone()].iter().enumerate() {
+            let [a, b, c] = triangle.map(|i| &vertices[i as usize]);
 
-        let ap = transform * a.position;
-        let bp = transform * b.position;
-        let cp = transform * c.position;
-        let an = (transform.rotation * a.normal).normalized();
-        
-        if an.dot(camera.position - ap) <= 0. { continue }
+            if a.normal.dot(camera.position - a.position) <= 0. { continue }
 
-        project_triangle(
-            width, height,
-            pixels,
-            zbuffer,
-            camera.mat * ap.extend(1.),
-            camera.mat * bp.extend(1.),
-            camera.mat * cp.extend(1.),
-            a.uv, b.uv, c.uv,
-            object.texture,
-            &object.gimap
-        )
+            let [auv, buv, cuv] = triangle.map(|i| object.model.mesh.vertices[i as usize].uv);
+            let color = triangle.map(|i| object.model.mesh.vertices[i as usize].color);
+            if let Some(triangle) = project_triangle(
+                width, height,
+                object_id,
+                primitive.triangles.start + triangle_id,
+                primitive.material,
+                object.material_at(primitive.material).alpha_mode == AlphaMode::Blend,
+                a.clip, b.clip, c.clip,
+                auv, buv, cuv,
+                [a.position, b.position, c.position],
+                [a.normal, b.normal, c.normal],
+                color
+            ) {
+                triangles.push(triangle)
+            }
+        }
     }
 }
 #[inline(always)]
 fn project_triangle(
     width: i32, height: i32,
-    pixels: &mut [u8],
-    zbuffer: &mut [f32],
+    object: usize,
+    triangle: usize,
+    material: usize,
+    blend: bool,
     mut a: Vec4, mut b: Vec4, mut c: Vec4,
     mut auv: Vec2, mut buv: Vec2, mut cuv: Vec2,
-    diffuse: &Texture,
-    gimap: &GIMap
-) {
-    if a.w <= 0. || b.w <= 0. || c.w <= 0. { return }
+    position: [Vec3;3],
+    normal: [Vec3;3],
+    color: [Vec3;3]
+) -> Option<ScreenTriangle> {
+    if a.w <= 0. || b.w <= 0. || c.w <= 0. { return None }
     a.x /= a.w;  a.y /= a.w;  a.z /= a.w;
     b.x /= b.w;  b.y /= b.w;  b.z /= b.w;
     c.x /= c.w;  c.y /= c.w;  c.z /= c.w;
     auv /= a.w;  buv /= b.w;  cuv /= c.w;
     a.w = 1./a.w;  b.w = 1./b.w;  c.w = 1./c.w;
-    raster_triangle(
-        width, height,
-        pixels,
-        zbuffer,
-        ((a.x + 1.) * 0.5 * width as f32) as i32,
-        ((a.y + 1.) * 0.5 * height as f32) as i32,
-        ((b.x + 1.) * 0.5 * width as f32) as i32,
-         ((b.y + 1.) * 0.5 * height as f32) as i32,
-        ((c.x + 1.) * 0.5 * width as f32) as i32,
-        ((c.y + 1.) * 0.5 * height as f32) as i32,
-        Vec3::new(a.z, b.z, c.z),
-        Vec3::new(a.w, b.w, c.w),
-        Vec3::new(auv.x, buv.x, cuv.x),
-        Vec3::new(auv.y, buv.y, cuv.y),
-        diffuse,
-        gimap
-    )
+    let sa = Vec2::new((a.x + 1.) * 0.5 * width as f32, (a.y + 1.) * 0.5 * height as f32);
+    let sb = Vec2::new((b.x + 1.) * 0.5 * width as f32, (b.y + 1.) * 0.5 * height as f32);
+    let sc = Vec2::new((c.x + 1.) * 0.5 * width as f32, (c.y + 1.) * 0.5 * height as f32);
+    let area = (sb.x - sa.x) * (sc.y - sa.y) - (sb.y - sa.y) * (sc.x - sa.x);
+    if area == 0. { return None }
+    Some(ScreenTriangle {
+        object,
+        triangle,
+        material,
+        blend,
+        a: sa, b: sb, c: sc,
+        z: Vec3::new(a.z, b.z, c.z),
+        w: Vec3::new(a.w, b.w, c.w),
+        uvx: Vec3::new(auv.x, buv.x, cuv.x),
+        uvy: Vec3::new(auv.y, buv.y, cuv.y),
+        position: [position[0] * a.w, position[1] * b.w, position[2] * c.w],
+        normal: [normal[0] * a.w, normal[1] * b.w, normal[2] * c.w],
+        color: [color[0] * a.w, color[1] * b.w, color[2] * c.w],
+        dbdx: Vec3::new(sb.y - sc.y, sc.y - sa.y, sa.y - sb.y) * (1. / area),
+        dbdy: Vec3::new(sc.x - sb.x, sa.x - sc.x, sb.x - sa.x) * (1. / area)
+    })
 }
+/// Moves the blended triangles after the opaque ones, sorted back to front
+pub fn sort_transparent(triangles: &mut [ScreenTriangle]) {
+    triangles.sort_by(|a, b| a.blend.cmp(&b.blend).then_with(||
+        if a.blend { (b.z.x + b.z.y + b.z.z).total_cmp(&(a.z.x + a.z.y + a.z.z)) } else { std::cmp::Ordering::Equal }
+    ))
+}
+/// Clips world space lines against the near side of the camera and projects them to the screen
+pub fn project_lines(
+    width: i32, height: i32,
+    lines: &[Line],
+    camera: &Camera,
+    out: &mut Vec<ScreenLine>
+) {
+    let to_screen = |p: Vec4| Vec2::new((p.x / p.w + 1.) * 0.5 * width as f32, (p.y / p.w + 1.) * 0.5 * height as f32);
+    let lerp = |a: Vec4, b: Vec4, t: f32| Vec4::new(
+        a.x + (b.x - a.x) * t, a.y + (b.y - a.y) * t, a.z + (b.z - a.z) * t, a.w + (b.w - a.w) * t
+    );
+    for line in lines.iter() {
+        let mut a = camera.mat * line.a.extend(1.);
+        let mut b = camera.mat * line.b.extend(1.);
+        if a.w < NEAR && b.w < NEAR { continue }
+        if a.w < NEAR { a = lerp(a, b, (NEAR - a.w) / (b.w - a.w)) }
+        if b.w < NEAR { b = lerp(b, a, (NEAR - b.w) / (a.w - b.w)) }
+        out.push(ScreenLine {
+            a: to_screen(a),
+            b: to_screen(b),
+            za: a.z / a.w,
+            zb: b.z / b.w,
+            color: line.color
+        })
+    }
+}
+/// Calls `f` with the index of every tile overlapping the screen rect `min max`
 #[inline(always)]
-fn raster_triangle(
+fn overlapping_tiles(width: i32, height: i32, min: Vec2, max: Vec2, mut f: impl FnMut(usize)) {
+    if max.x < 0. || max.y < 0. || min.x >= width as f32 || min.y >= height as f32 { return }
+    let tiles_x = (width + TILE_SIZE - 1) / TILE_SIZE;
+    let tminx = (min.x as i32).max(0) / TILE_SIZE;
+    let tminy = (min.y as i32).max(0) / TILE_SIZE;
+    let tmaxx = (max.x as i32).min(width - 1) / TILE_SIZE;
+    let tmaxy = (max.y as i32).min(height - 1) / TILE_SIZE;
+    for ty in tminy..=tmaxy {
+        for tx in tminx..=tmaxx {
+            f((ty * tiles_x + tx) as usize)
+        }
+    }
+}
+pub fn bin(
     width: i32, height: i32,
-    pixels: &mut [u8],
-    zbuffer: &mut [f32],
-    ax: i32, ay: i32,
-    bx: i32, by: i32,
-    cx: i32, cy: i32,
-    z: Vec3,
-    w: Vec3,
-    uvx: Vec3,
-    uvy: Vec3,
-    diffuse: &Texture,
-    gimap: &GIMap
+    triangles: &[ScreenTriangle],
+    lines: &[ScreenLine],
+    tiles: &mut [Tile]
 ) {
-    let max_width = width - 1;
-    let max_height = height - 1;
-    
-    let minx = max_width.min(ax).max(0).min(bx).max(0).min(cx).max(0);
-    let mut miny = max_height.min(ay).max(0).min(by).max(0).min(cy).max(0);
-    let maxx = 0.max(ax).min(max_width).max(bx).min(max_width).max(cx).min(max_width);
-    let maxy = 0.max(ay).min(max_height).max(by).min(max_height).max(cy).min(max_height);
-
-    let l1x = cx as f32 - ax as f32;
-    let l1y = bx as f32 - ax as f32;
-    let mut l1z;
-    let l2x = cy as f32 - ay as f32;
-    let l2y = by as f32 - ay as f32;
-    let mut l2z;
-    
-    let mut ux; let mut uy;
-    let uz = (l1x * l2y) - (l1y * l2x);
-    if uz.abs() < 1. { return }
-
-    let mut i = (miny * width) as usize * 4;
-    let mut _i = (miny * width) as usize;
-    let mut x;
-    let line_width = (max_width - maxx) as usize * 4;
-    let _line_width = (max_width - maxx) as usize;
-    let line_offset = minx as usize * 4;
-    let sm_size = (gimap.width - 1)as f32;
-    let df_size = diffuse.size - 1.;
-    let mut baryc = Vec3::default();
-    
-    while miny <= maxy {
-        l2z = ay as f32 - miny as f32;
-        x = minx;
-        i += line_offset;
-        _i += minx as usize;
-        while x <= maxx {
-            l1z = ax as f32 - x as f32;
-            ux = (l1y * l2z) - (l1z * l2y);
-            uy = (l1z * l2x) - (l1x * l2z);
-
-            baryc.x = 1.-(ux+uy)/uz;
-            if baryc.x < 0. { x += 1; i += 4; _i += 1; continue }
-
-            baryc.y = uy/uz;
-            if baryc.y < 0. { x += 1; i += 4; _i += 1; continue }
-
-            baryc.z = ux/uz;
-            if baryc.z < 0. { x += 1; i += 4; _i += 1; continue }
-
-            let z = z.dot(baryc);
-            if zbuffer[_i] >= z {
-                let w = w.dot(baryc);
-                let smuv = Vec2::new(uvx.dot(baryc), uvy.dot(baryc)) * sm_size / w;
-                let dfuv = Vec2::new(uvx.dot(baryc), uvy.dot(baryc)) * df_size / w;
-                let tex_color =
-                    diffuse.pixels[dfuv.y as usize][dfuv.x as usize] *
-                    gimap.get_value(smuv.x, smuv.y) * 255.;
-                pixels[i    ] = tex_color.x as u8;
-                pixels[i + 1] = tex_color.y as u8;
-                pixels[i + 2] = tex_color.z as u8;
-                zbuffer[_i] = z;
+    for tile in tiles.iter_mut() {
+        tile.triangles.clear();
+        tile.lines.clear()
+    }
+    for (i, t) in triangles.iter().enumerate() {
+        let min = Vec2::new(t.a.x.min(t.b.x).min(t.c.x), t.a.y.min(t.b.y).min(t.c.y));
+        let max = Vec2::new(t.a.x.max(t.b.x).max(t.c.x), t.a.y.max(t.b.y).max(t.c.y));
+        overlapping_tiles(width, height, min, max, |tile| tiles[tile].triangles.push(i as u32))
+    }
+    for (i, l) in lines.iter().enumerate() {
+        let min = Vec2::new(l.a.x.min(l.b.x), l.a.y.min(l.b.y));
+        let max = Vec2::new(l.a.x.max(l.b.x), l.a.y.max(l.b.y));
+        overlapping_tiles(width, height, min, max, |tile| tiles[tile].lines.push(i as u32))
+    }
+}
+pub fn draw(
+    tile: &mut Tile,
+    triangles: &[ScreenTriangle],
+    lines: &[ScreenLine],
+    objects: &[Object],
+    camera: &Camera,
+    dir_light: &DirectionalLight,
+    view: ViewMode
+) {
+    let Tile { x: x0, y: y0, width, height, pixels, zbuffer, overdraw, triangles: binned, lines: binned_lines } = tile;
+    let (x0, y0, width, height) = (*x0, *y0, *width, *height);
+    clear(pixels);
+    zbuffer.fill(f32::MAX);
+    let show_overdraw = view == ViewMode::Overdraw;
+    if show_overdraw { overdraw.fill(0) }
+    let light = dir_light.direction.normalized();
+    for &id in binned.iter() {
+        let t = &triangles[id as usize];
+        let object = &objects[t.object];
+        let material = object.material_at(t.material);
+        let gimap = &object.gimap;
+        // Same texel centers as the rasterization of the map
+        let sm_size = Vec2::new(gimap.width as f32, gimap.height as f32);
+        let baked = gimap.is_baked();
+        let dwdx = t.w.dot(t.dbdx);
+        let dwdy = t.w.dot(t.dbdy);
+        raster_triangle(
+            x0, y0, x0 + width, y0 + height,
+            t.a, t.b, t.c,
+            |x, y, baryc| {
+                let i = ((y - y0) * width + x - x0) as usize;
+                if show_overdraw { overdraw[i] += 1 }
+                let z = t.z.dot(baryc);
+                if zbuffer[i] < z { return }
+                let w = t.w.dot(baryc);
+                let uv = Vec2::new(t.uvx.dot(baryc), t.uvy.dot(baryc)) / w;
+                let duvdx = Vec2::new(t.uvx.dot(t.dbdx) - uv.x * dwdx, t.uvy.dot(t.dbdx) - uv.y * dwdx) / w;
+                let duvdy = Vec2::new(t.uvx.dot(t.dbdy) - uv.x * dwdy, t.uvy.dot(t.dbdy) - uv.y * dwdy) / w;
+                let position = interpolate(&t.position, baryc) * (1. / w);
+                let mut surface = material.surface(uv, duvdx, duvdy);
+                surface.albedo = mul(surface.albedo, interpolate(&t.color, baryc) * (1. / w));
+                let alpha = match material.alpha_mode {
+                    AlphaMode::Opaque => 1.,
+                    AlphaMode::Mask(cutoff) => if surface.alpha < cutoff { return } else { 1. },
+                    AlphaMode::Blend => surface.alpha
+                };
+                let mut normal = interpolate(&t.normal, baryc).normalized();
+                if let Some(tangent_normal) = surface.normal {
+                    let dpdx = (interpolate(&t.position, t.dbdx) - position * dwdx) * (1. / w);
+                    let dpdy = (interpolate(&t.position, t.dbdy) - position * dwdy) * (1. / w);
+                    normal = perturb_normal(normal, tangent_normal, dpdx, dpdy, duvdx, duvdy)
+                }
+                let smuv = Vec2::new((uv.x * sm_size.x).max(0.).min(sm_size.x - 1.), (uv.y * sm_size.y).max(0.).min(sm_size.y - 1.));
+                let color = match view {
+                    ViewMode::Shaded | ViewMode::Overdraw => {
+                        let view = (camera.position - position).normalized();
+                        let (diffuse, specular) = surface.cook_torrance(normal, view, light);
+                        let diffuse = if baked { surface.albedo * gimap.get_value(smuv.x, smuv.y) } else { diffuse };
+                        diffuse + specular + surface.ambient() + surface.emissive
+                    },
+                    ViewMode::Lighting => {
+                        let v = if baked { gimap.get_value(smuv.x, smuv.y) } else { 0. };
+                        Vec3::new(v, v, v)
+                    },
+                    ViewMode::Albedo => surface.albedo,
+                    ViewMode::Normals => display(Vec3::new(normal.x * 0.5 + 0.5, normal.y * 0.5 + 0.5, normal.z * 0.5 + 0.5)),
+                    ViewMode::Uvs => display(Vec3::new(uv.x.rem_euclid(1.), uv.y.rem_euclid(1.), 0.)),
+                    ViewMode::TexelDensity => {
+                        let v = if (smuv.x as usize + smuv.y as usize) % 2 == 0 { 0.25 } else { 0.75 };
+                        display(Vec3::new(v, v, v))
+                    },
+                    ViewMode::Depth => {
+                        let v = 1. - ((1. / w) / NEAR).ln() / (FAR / NEAR).ln();
+                        display(Vec3::new(v, v, v))
+                    },
+                    ViewMode::TriangleIds => display(id_color(t.triangle)),
+                    ViewMode::ObjectIds => display(id_color(t.object))
+                };
+                pixels[i * 4    ] = color.x * alpha + pixels[i * 4    ] * (1. - alpha);
+                pixels[i * 4 + 1] = color.y * alpha + pixels[i * 4 + 1] * (1. - alpha);
+                pixels[i * 4 + 2] = color.z * alpha + pixels[i * 4 + 2] * (1. - alpha);
+                if !t.blend { zbuffer[i] = z }
             }
-
-            x += 1;
-            i += 4;
-            _i += 1;
+        )
+    }
+    if show_overdraw {
+        for (i, &count) in overdraw.iter().enumerate() {
+            let [r, g, b] = HEATMAP[(count as usize).min(HEATMAP.len() - 1)];
+            let color = display(Vec3::new(r, g, b));
+            pixels[i * 4    ] = color.x;
+            pixels[i * 4 + 1] = color.y;
+            pixels[i * 4 + 2] = color.z;
         }
-        miny += 1;
-        i += line_width;
-        _i += _line_width
     }
-}
\ No newline at end of file
+    for &id in binned_lines.iter() {
+        let l = &lines[id as usize];
+        raster_line(
+            x0, y0, x0 + width, y0 + height,
+            l.a, l.b,
+            |x, y, t| {
+                let i = ((y - y0) * width + x - x0) as usize;
+                if l.za + (l.zb - l.za) * t > zbuffer[i] + LINE_DEPTH_BIAS { return }
+                pixels[i * 4    ] = l.color.x;
+                pixels[i * 4 + 1] = l.color.y;
+                pixels[i * 4 + 2] = l.color.z;
+            }
+        )
+    }
+}
+/// Linear color that is displayed as `c` by the output encoding
+#[inline(always)]
+fn display(c: Vec3) -> Vec3 {
+    Vec3::new(srgb_to_linear(c.x), srgb_to_linear(c.y), srgb_to_linear(c.z))
+}
+#[inline(always)]
+fn id_color(id: usize) -> Vec3 {
+    let h = (id as u32).wrapping_add(1).wrapping_mul(0x9E37_79B9);
+    let h = (h ^ (h >> 15)).wrapping_mul(0x85EB_CA6B);
+    Vec3::new((h & 0xff) as f32 / 255., (h >> 8 & 0xff) as f32 / 255., (h >> 16 & 0xff) as f32 / 255.)
+}
+#[inline(always)]
+fn interpolate(v: &[Vec3;3], baryc: Vec3) -> Vec3 {
+    v[0] * baryc.x + v[1] * baryc.y + v[2] * baryc.z
+}
+#[inline(always)]
+pub fn cross(a: Vec3, b: Vec3) -> Vec3 {
+    Vec3::new(a.y * b.z - a.z * b.y, a.z * b.x - a.x * b.z, a.x * b.y - a.y * b.x)
+}
+/// Applies a tangent space normal using a cotangent frame built from the screen space
+/// derivatives of the position and uv, so meshes don't need stored tangents
+#[inline(always)]
+fn perturb_normal(n: Vec3, tangent_normal: Vec3, dpdx: Vec3, dpdy: Vec3, duvdx: Vec2, duvdy: Vec2) -> Vec3 {
+    let dpdy_perp = cross(dpdy, n);
+    let dpdx_perp = cross(n, dpdx);
+    let t = dpdy_perp * duvdx.x + dpdx_perp * duvdy.x;
+    let b = dpdy_perp * duvdx.y + dpdx_perp * duvdy.y;
+    let scale = t.dot(t).max(b.dot(b));
+    if scale <= 0. { return n }
+    let scale = 1. / scale.sqrt();
+    (t * (tangent_normal.x * scale) + b * (tangent_normal.y * scale) + n * tangent_normal.z).normalized()
+}
+pub fn resolve(
+    width: i32,
+    pixels: &mut [f32],
+    tiles: &[Tile]
+) {
+    for tile in tiles.iter() {
+        let row = tile.width as usize * 4;
+        for y in 0..tile.height {
+            let src = y as usize * row;
+            let dst = (((tile.y + y) * width + tile.x) * 4) as usize;
+            pixels[dst..dst + row].copy_from_slice(&tile.pixels[src..src + row])
+        }
+    }
+}
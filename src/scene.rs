@@ -0,0 +1,276 @@
+use std::{path::{Path, PathBuf}, collections::HashMap};
+use math::{Vec2, Vec3, Vec4, Quaternion, Transform, deg_to_rad};
+use serde::Deserialize;
+
+use crate::{
+    object::{Object, Model, has_extension},
+    hierarchy::Hierarchy,
+    camera::Camera,
+    dir_light::DirectionalLight,
+    material::{Material, TextureBinding, AlphaMode},
+    texture::{Texture, Sampler, ColorSpace},
+    gimap::GI_TEXTURE_SCALE,
+    error::{Error, Result}
+};
+
+/// JSON scene description, paths are relative to the scene file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    #[serde(default)]
+    camera: CameraDesc,
+    #[serde(default)]
+    light: LightDesc,
+    #[serde(default)]
+    gi: GISettings,
+    /// Hierarchy nodes without an object, parents of the objects moved together
+    #[serde(default)]
+    groups: Vec<GroupDesc>,
+    objects: Vec<ObjectDesc>,
+    /// Group or object moved by the arrow keys, defaults to the first group
+    moved: Option<String>
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CameraDesc {
+    /// Point the camera orbits around
+    target: [f32;3],
+    /// Degrees
+    pitch: f32,
+    yaw: f32,
+    distance: f32
+}
+impl Default for CameraDesc {
+    fn default() -> Self {
+        Self { target: [0., 0., 0.], pitch: -20., yaw: 0., distance: 10. }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LightDesc {
+    direction: [f32;3]
+}
+impl Default for LightDesc {
+    fn default() -> Self {
+        let direction = DirectionalLight::default().direction;
+        Self { direction: [direction.x, direction.y, direction.z] }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GISettings {
+    /// Runs the global illumination thread, surfaces keep the direct lighting otherwise
+    pub enabled: bool,
+    /// Defaults to `GI_TEXTURE_SCALE`
+    pub texture_scale: f32
+}
+impl Default for GISettings {
+    fn default() -> Self {
+        Self { enabled: true, texture_scale: GI_TEXTURE_SCALE }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TransformDesc {
+    translation: [f32;3],
+    /// Euler angles in degrees, applied around z then x then y
+    rotation: [f32;3],
+    scale: [f32;3]
+}
+impl Default for TransformDesc {
+    fn default() -> Self {
+        Self { translation: [0., 0., 0.], rotation: [0., 0., 0.], scale: [1., 1., 1.] }
+    }
+}
+impl TransformDesc {
+    fn transform(&self) -> Transform {
+        let axis = |x: f32, y: f32, z: f32, degrees: f32| {
+            let (s, c) = (deg_to_rad(degrees) * 0.5).sin_cos();
+            Quaternion { x: x * s, y: y * s, z: z * s, w: c }
+        };
+        let [x, y, z] = self.rotation;
+        Transform {
+            translation: self.translation.into(),
+            rotation: axis(0., 1., 0., y) * axis(1., 0., 0., x) * axis(0., 0., 1., z),
+            scale: self.scale.into()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GroupDesc {
+    name: String,
+    #[serde(default)]
+    transform: TransformDesc,
+    /// Name of a group or object declared before
+    parent: Option<String>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ObjectDesc {
+    /// Defaults to the name of the model
+    name: Option<String>,
+    /// glTF, OBJ, PLY or STL file, objects using the same file share its model
+    mesh: PathBuf,
+    /// Replaces every material of the model, not allowed with `scene`
+    material: Option<MaterialDesc>,
+    #[serde(default)]
+    transform: TransformDesc,
+    parent: Option<String>,
+    /// Imports the nodes of a glTF file as objects under this one instead of merging its meshes
+    #[serde(default)]
+    scene: bool
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct MaterialDesc {
+    /// sRGB image
+    texture: Option<PathBuf>,
+    /// Linear RGBA, blended when the alpha is below 1
+    color: [f32;4],
+    metallic: f32,
+    roughness: f32,
+    emissive: [f32;3]
+}
+impl Default for MaterialDesc {
+    fn default() -> Self {
+        let material = Material::default();
+        let c = material.base_color;
+        Self { texture: None, color: [c.x, c.y, c.z, c.w], metallic: material.metallic, roughness: material.roughness, emissive: [0., 0., 0.] }
+    }
+}
+
+/// Everything the engine is built from
+pub struct Scene {
+    pub objects: Vec<Object>,
+    pub hierarchy: Hierarchy,
+    pub camera: Camera,
+    pub dir_light: DirectionalLight,
+    pub gi: GISettings,
+    /// Hierarchy node moved by the arrow keys
+    pub moved: Option<usize>,
+    /// Shared by the objects using the same file
+    models: HashMap<PathBuf, &'static Model>,
+    textures: HashMap<PathBuf, &'static Texture>
+}
+impl Scene {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path).map_err(|error| Error::from(error).in_file(path))?;
+        let file = serde_json::from_str::<SceneFile>(&source)
+            .map_err(|error| Error::decode(error.to_string()).in_file(path))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        if !(file.gi.texture_scale.is_finite() && file.gi.texture_scale > 0.) {
+            return Err(Error::validation(format!("texture scale {} is not positive", file.gi.texture_scale)).in_file(path).at("gi"))
+        }
+
+        let mut camera = Camera::new();
+        camera.translation = file.camera.target.into();
+        camera.rotation = Vec2::new(deg_to_rad(file.camera.pitch), deg_to_rad(file.camera.yaw));
+        camera.distance = file.camera.distance;
+        let mut scene = Self {
+            objects: Vec::new(),
+            hierarchy: Hierarchy::default(),
+            camera,
+            dir_light: DirectionalLight { direction: file.light.direction.into() },
+            gi: file.gi,
+            moved: None,
+            models: HashMap::new(),
+            textures: HashMap::new()
+        };
+
+        for (i, group) in file.groups.iter().enumerate() {
+            let transform = group.transform.transform();
+            let parent = scene.parent(group.parent.as_deref(), &transform).map_err(|error| error.in_file(path).at(format!("groups[{i}]")))?;
+            scene.hierarchy.add(group.name.clone(), transform, parent, None);
+        }
+        for (i, desc) in file.objects.iter().enumerate() {
+            let at = || format!("objects[{i}]");
+            let parent = scene.parent(desc.parent.as_deref(), &desc.transform.transform()).map_err(|error| error.in_file(path).at(at()))?;
+            let mesh = dir.join(&desc.mesh);
+            if desc.scene {
+                if desc.material.is_some() {
+                    return Err(Error::validation("material on an imported scene").in_file(path).at(at()))
+                }
+                let name = desc.name.clone().unwrap_or_else(|| desc.mesh.display().to_string());
+                let node = scene.hierarchy.add(name, desc.transform.transform(), parent, None);
+                Object::load_scene(&mesh, None, &mut scene.hierarchy, Some(node), scene.gi.texture_scale, &mut scene.objects)?;
+                continue
+            }
+            let model = scene.model(&mesh)?;
+            if model.lightmap_size.is_some() && desc.material.as_ref().map_or(false, |material| material.texture.is_some()) {
+                return Err(Error::unsupported("base color texture on a mesh without uvs").in_file(path).at(at()))
+            }
+            let material = desc.material.as_ref().map(|material| scene.material(material, dir)).transpose()?;
+            let name = desc.name.clone().unwrap_or_else(|| model.name.clone());
+            let node = scene.hierarchy.add(name, desc.transform.transform(), parent, None);
+            let object = Object::new(model, material, scene.hierarchy.nodes[node].world.clone(), scene.gi.texture_scale);
+            scene.hierarchy.nodes[node].object = Some(scene.objects.len());
+            scene.objects.push(object)
+        }
+        scene.moved = match &file.moved {
+            Some(name) => Some(scene.hierarchy.find(name).ok_or_else(|| Error::validation(format!("unknown node {name}")).in_file(path).at("moved"))?),
+            None => file.groups.first().and_then(|group| scene.hierarchy.find(&group.name))
+        };
+        Ok(scene)
+    }
+    /// Adds the glTF scene or the model in the file at the root of the hierarchy
+    pub fn import(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        if has_extension(path, "gltf") || has_extension(path, "glb") {
+            Object::load_scene(path, None, &mut self.hierarchy, None, self.gi.texture_scale, &mut self.objects)?
+        } else {
+            let model = self.model(path)?;
+            let object = Object::new(model, None, Transform::default(), self.gi.texture_scale);
+            self.hierarchy.add(model.name.clone(), Transform::default(), None, Some(self.objects.len()));
+            self.objects.push(object)
+        }
+        Ok(())
+    }
+    /// Node named `name`, checked to place `local` exactly
+    fn parent(&self, name: Option<&str>, local: &Transform) -> Result<Option<usize>> {
+        let parent = name.map(|name| self.hierarchy.find(name).ok_or_else(|| Error::validation(format!("unknown parent {name}"))))
+            .transpose()?;
+        if self.hierarchy.shears(parent, local) {
+            return Err(Error::unsupported("rotation under a non uniformly scaled parent"))
+        }
+        Ok(parent)
+    }
+    fn model(&mut self, path: &Path) -> Result<&'static Model> {
+        if let Some(&model) = self.models.get(path) { return Ok(model) }
+        let model = &*Box::leak(Box::new(Model::load(path, None)?));
+        self.models.insert(path.to_path_buf(), model);
+        Ok(model)
+    }
+    fn material(&mut self, desc: &MaterialDesc, dir: &Path) -> Result<Material> {
+        let texture = match &desc.texture {
+            Some(texture) => {
+                let path = dir.join(texture);
+                let texture = match self.textures.get(&path) {
+                    Some(&texture) => texture,
+                    None => &*Box::leak(Box::new(Texture::load(&path, ColorSpace::Srgb)?))
+                };
+                self.textures.insert(path, texture);
+                Some(TextureBinding { texture, sampler: Sampler::default() })
+            },
+            None => None
+        };
+        let [r, g, b, a] = desc.color;
+        Ok(Material {
+            base_color: Vec4::new(r, g, b, a),
+            base_color_texture: texture,
+            metallic: desc.metallic,
+            roughness: desc.roughness,
+            emissive: Vec3::from(desc.emissive),
+            alpha_mode: if a < 1. { AlphaMode::Blend } else { AlphaMode::Opaque },
+            ..Default::default()
+        })
+    }
+}
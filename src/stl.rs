@@ -0,0 +1,163 @@
+use std::path::Path;
+use math::{Vec2, Vec3};
+
+use crate::{
+    object::Model,
+    mesh::{Mesh, Vertex},
+    texture::Texture,
+    render::cross,
+    error::{Error, Result}
+};
+
+/// Loads an ASCII or binary STL file as a single model with uvs generated for the lightmap, so without `texture`.
+/// Facets are flat shaded, their normal comes from the winding since many exporters leave the stored one zero
+pub fn load(path: &Path, texture: Option<&'static Texture>) -> Result<Model> {
+    if texture.is_some() {
+        return Err(Error::unsupported("base color texture on a mesh without uvs").in_file(path))
+    }
+    let data = std::fs::read(path).map_err(|error| Error::from(error).in_file(path))?;
+    let facets = read(&data, path)?;
+    let mut vertices = Vec::with_capacity(facets.len() * 3);
+    for p in facets.iter() {
+        let normal = cross(p[1] - p[0], p[2] - p[0]);
+        let normal = if normal.dot(normal) > 0. { normal.normalized() } else { Vec3::new(0., 1., 0.) };
+        vertices.extend(p.map(|position| Vertex { position, normal, uv: Vec2::default(), color: Vec3::new(1., 1., 1.) }))
+    }
+    let indices = (0..facets.len() as u32).map(|i| [i * 3, i * 3 + 1, i * 3 + 2]).collect();
+    let mut mesh = Mesh::new(vertices, indices);
+    let lightmap_size = mesh.unwrap_uvs();
+    let name = path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+    Ok(Model::from_mesh(name, mesh.with_adjacency(), None, Some(lightmap_size)))
+}
+
+/// Corners of the facets of an ASCII or binary file, `path` only shows up in error messages
+fn read(data: &[u8], path: &Path) -> Result<Vec<[Vec3;3]>> {
+    // Binary files may start with `solid` too, their size gives them away
+    let count = data.get(80..84).map(|count| u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize);
+    let binary = count.map_or(false, |count| data.len() == 84 + count * 50) || !data.starts_with(b"solid");
+    let facets = if binary { read_binary(data, path)? } else { read_ascii(data, path)? };
+    if facets.is_empty() {
+        return Err(Error::validation("no facets").in_file(path))
+    }
+    Ok(facets)
+}
+
+/// 80 byte header, facet count, then per facet a normal, 3 vertices and 2 attribute bytes
+fn read_binary(data: &[u8], path: &Path) -> Result<Vec<[Vec3;3]>> {
+    let count = data.get(80..84)
+        .map(|count| u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize)
+        .ok_or_else(|| Error::decode("too short for a binary STL file").in_file(path))?;
+    if data.len() < 84 + count * 50 {
+        return Err(Error::validation(format!("truncated, {count} facets expected")).in_file(path))
+    }
+    let float = |offset: usize| f32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]);
+    let vec3 = |offset: usize| Vec3::new(float(offset), float(offset + 4), float(offset + 8));
+    Ok((0..count)
+        .map(|i| {
+            let facet = 84 + i * 50;
+            [vec3(facet + 12), vec3(facet + 24), vec3(facet + 36)]
+        })
+        .collect())
+}
+
+/// `facet normal`, `outer loop`, 3 `vertex x y z`, `endloop`, `endfacet`
+fn read_ascii(data: &[u8], path: &Path) -> Result<Vec<[Vec3;3]>> {
+    let text = std::str::from_utf8(data).map_err(|_| Error::decode("ASCII STL file is not text").in_file(path))?;
+    let mut tokens = text.split_ascii_whitespace();
+    let mut facets = Vec::new();
+    let mut corners = Vec::with_capacity(3);
+    while let Some(token) = tokens.next() {
+        let at = || format!("facet {}", facets.len());
+        match token {
+            "vertex" => {
+                let mut float = || tokens.next()
+                    .and_then(|value| value.parse::<f32>().ok())
+                    .ok_or_else(|| Error::validation("expected 3 numbers").in_file(path).at(at()));
+                corners.push(Vec3::new(float()?, float()?, float()?))
+            },
+            "endfacet" => {
+                let [a, b, c] = corners[..] else {
+                    return Err(Error::validation(format!("facet with {} vertices", corners.len())).in_file(path).at(at()))
+                };
+                facets.push([a, b, c]);
+                corners.clear()
+            },
+            _ => {}
+        }
+    }
+    Ok(facets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASCII: &str = "solid square
+facet normal 0 0 1
+outer loop
+vertex 0 0 0
+vertex 1 0 0
+vertex 1 1 0
+endloop
+endfacet
+facet normal 0 0 1
+outer loop
+vertex 0 0 0
+vertex 1 1 0
+vertex 0 1 0
+endloop
+endfacet
+endsolid square
+";
+
+    /// `count` is written in the header, `facets` follow it
+    fn binary(header: &[u8], count: u32, facets: &[[[f32;3];3]]) -> Vec<u8> {
+        let mut data = header.to_vec();
+        data.resize(80, b' ');
+        data.extend(count.to_le_bytes());
+        for facet in facets {
+            data.extend([0f32;3].iter().flat_map(|v| v.to_le_bytes()));
+            data.extend(facet.iter().flatten().flat_map(|v| v.to_le_bytes()));
+            data.extend([0;2])
+        }
+        data
+    }
+
+    fn xyz(v: Vec3) -> [f32;3] {
+        [v.x, v.y, v.z]
+    }
+
+    fn path() -> &'static Path {
+        Path::new("test.stl")
+    }
+
+    #[test]
+    fn reads_ascii() {
+        let facets = read(ASCII.as_bytes(), path()).unwrap();
+        assert_eq!(facets.len(), 2);
+        assert_eq!(xyz(facets[1][2]), [0., 1., 0.]);
+    }
+
+    #[test]
+    fn reads_binary_starting_with_solid() {
+        let data = binary(b"solid exported by a tool that writes it anyway", 1, &[[[0., 0., 0.], [2., 0., 0.], [0., 3., 0.]]]);
+        let facets = read(&data, path()).unwrap();
+        assert_eq!(facets.len(), 1);
+        assert_eq!(xyz(facets[0][1]), [2., 0., 0.]);
+        assert_eq!(xyz(facets[0][2]), [0., 3., 0.]);
+    }
+
+    #[test]
+    fn rejects_truncated_binary() {
+        let data = binary(b"binary", 2, &[[[0., 0., 0.], [1., 0., 0.], [0., 1., 0.]]]);
+        assert!(read(&data, path()).is_err());
+        assert!(read(&data[..60], path()).is_err());
+    }
+
+    #[test]
+    fn rejects_incomplete_ascii_facets() {
+        let text = ASCII.replacen("vertex 1 1 0\n", "", 1);
+        assert!(read(text.as_bytes(), path()).is_err());
+        assert!(read(b"solid empty\nendsolid empty\n", path()).is_err());
+    }
+}
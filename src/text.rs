@@ -13,6 +13,7 @@ impl Log {
     }
 }
 
+/// Draws `text` from the top left corner of the RGBA image, clipped to it, glyphs missing from the font are left blank
 pub fn render_text(
     width: usize,
     pixels: &mut [u8],
@@ -21,6 +22,7 @@ pub fn render_text(
     let weight = FontWeight::Regular;
     let size = RasterHeight::Size16;
     let char_width = get_raster_width(weight, size);
+    let height = pixels.len() / 4 / width.max(1);
     let mut offset_x = 0;
     let mut offset_y = 0;
     for c in text.chars() {
@@ -29,15 +31,34 @@ pub fn render_text(
             offset_y += char_width * 2;
             continue
         }
-        let char_raster = get_raster(c, weight, size).unwrap();
-        for (y, row) in char_raster.raster().iter().enumerate() {
-            for (x, pixel) in row.iter().enumerate() {
-                let i = ((y + offset_y) * width + (x + offset_x)) * 4;
-                pixels[i  ] = *pixel;
-                pixels[i+1] = *pixel;
-                pixels[i+2] = *pixel;
+        if offset_y >= height { break }
+        if let Some(char_raster) = get_raster(c, weight, size) {
+            for (y, row) in char_raster.raster().iter().enumerate().take(height - offset_y) {
+                for (x, pixel) in row.iter().enumerate().take(width.saturating_sub(offset_x)) {
+                    let i = ((y + offset_y) * width + (x + offset_x)) * 4;
+                    pixels[i  ] = *pixel;
+                    pixels[i+1] = *pixel;
+                    pixels[i+2] = *pixel;
+                }
             }
         }
         offset_x += char_width
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clips_to_the_image() {
+        let (width, height) = (20, 40);
+        let mut pixels = vec![0;width * height * 4];
+        render_text(width, &mut pixels, "漢W and a line wider than the image\n\n\nmore lines than fit\nend");
+        // The missing glyph leaves its cell blank, the next one is drawn after it
+        let char_width = get_raster_width(FontWeight::Regular, RasterHeight::Size16);
+        let cell = |x: usize| (0..16).flat_map(move |y| (x..x + char_width).map(move |x| (y * width + x) * 4));
+        assert!(cell(0).all(|i| pixels[i] == 0));
+        assert!(cell(char_width).any(|i| pixels[i] > 0));
+    }
+}
@@ -1,22 +1,255 @@
 use std::path::Path;
-use image::{GenericImageView, Rgba};
-use math::{Vec2, Vec3};
+use gltf::texture::{WrappingMode, MagFilter, MinFilter};
+use gltf::image::Format;
+use image::{GenericImageView, DynamicImage, ColorType, ImageBuffer};
+use math::{Vec2, Vec4};
+
+use crate::{buffer::Buffer, error::{Error, Result}};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Wrap {
+    Repeat,
+    Clamp,
+    Mirror
+}
+impl Wrap {
+    #[inline(always)]
+    fn apply(self, i: i32, size: usize) -> usize {
+        let size = size as i32;
+        (match self {
+            Wrap::Repeat => i.rem_euclid(size),
+            Wrap::Clamp => i.max(0).min(size - 1),
+            Wrap::Mirror => {
+                let i = i.rem_euclid(size * 2);
+                if i < size { i } else { size * 2 - 1 - i }
+            }
+        }) as usize
+    }
+    /// Texel coordinate moved into `[-1, 2 * size]` onto the same texels, far away
+    /// coordinates would saturate when converted to `i32` and overflow the filter footprint
+    #[inline(always)]
+    fn reduce(self, x: f32, size: usize) -> f32 {
+        let size = size as f32;
+        match self {
+            Wrap::Repeat => x.rem_euclid(size),
+            Wrap::Clamp => x.max(-1.).min(size),
+            Wrap::Mirror => x.rem_euclid(size * 2.)
+        }
+    }
+}
+impl From<WrappingMode> for Wrap {
+    fn from(mode: WrappingMode) -> Self {
+        match mode {
+            WrappingMode::Repeat => Wrap::Repeat,
+            WrappingMode::ClampToEdge => Wrap::Clamp,
+            WrappingMode::MirroredRepeat => Wrap::Mirror
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Nearest,
+    Linear
+}
+
+#[derive(Clone, Copy)]
+pub struct Sampler {
+    pub wrap_u: Wrap,
+    pub wrap_v: Wrap,
+    pub mag_filter: Filter,
+    pub min_filter: Filter,
+    /// Filter between mip levels, `None` samples only the base level
+    pub mipmap_filter: Option<Filter>
+}
+impl Default for Sampler {
+    fn default() -> Self {
+        Self {
+            wrap_u: Wrap::Repeat,
+            wrap_v: Wrap::Repeat,
+            mag_filter: Filter::Linear,
+            min_filter: Filter::Linear,
+            mipmap_filter: Some(Filter::Linear)
+        }
+    }
+}
+impl Sampler {
+    pub fn from_gltf(sampler: &gltf::texture::Sampler) -> Self {
+        let default = Self::default();
+        let (min_filter, mipmap_filter) = match sampler.min_filter() {
+            Some(MinFilter::Nearest) => (Filter::Nearest, None),
+            Some(MinFilter::Linear) => (Filter::Linear, None),
+            Some(MinFilter::NearestMipmapNearest) => (Filter::Nearest, Some(Filter::Nearest)),
+            Some(MinFilter::LinearMipmapNearest) => (Filter::Linear, Some(Filter::Nearest)),
+            Some(MinFilter::NearestMipmapLinear) => (Filter::Nearest, Some(Filter::Linear)),
+            Some(MinFilter::LinearMipmapLinear) => (Filter::Linear, Some(Filter::Linear)),
+            None => (default.min_filter, default.mipmap_filter)
+        };
+        Self {
+            wrap_u: sampler.wrap_s().into(),
+            wrap_v: sampler.wrap_t().into(),
+            mag_filter: match sampler.mag_filter() {
+                Some(MagFilter::Nearest) => Filter::Nearest,
+                Some(MagFilter::Linear) => Filter::Linear,
+                None => default.mag_filter
+            },
+            min_filter,
+            mipmap_filter
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    Srgb,
+    Linear
+}
+
+#[inline(always)]
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+#[inline(always)]
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1. / 2.4) - 0.055 }
+}
+
+impl Buffer {
+    fn downsample(&self) -> Self {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        Self::from_fn(width, height, self.channels, |x, y, channel| {
+            let x0 = (x * 2).min(self.width - 1);
+            let x1 = (x * 2 + 1).min(self.width - 1);
+            let y0 = (y * 2).min(self.height - 1);
+            let y1 = (y * 2 + 1).min(self.height - 1);
+            (
+                self.texel(x0, y0)[channel] + self.texel(x1, y0)[channel] +
+                self.texel(x0, y1)[channel] + self.texel(x1, y1)[channel]
+            ) * 0.25
+        })
+    }
+    #[inline(always)]
+    fn color(&self, sampler: &Sampler, x: i32, y: i32) -> Vec4 {
+        let texel = self.texel(sampler.wrap_u.apply(x, self.width), sampler.wrap_v.apply(y, self.height));
+        match texel {
+            [r] => Vec4::new(*r, *r, *r, 1.),
+            [r, g, b] => Vec4::new(*r, *g, *b, 1.),
+            [r, g, b, a, ..] => Vec4::new(*r, *g, *b, *a),
+            _ => Vec4::new(0., 0., 0., 1.)
+        }
+    }
+    #[inline(always)]
+    fn sample(&self, sampler: &Sampler, filter: Filter, uv: Vec2) -> Vec4 {
+        let x = sampler.wrap_u.reduce(uv.x * self.width as f32, self.width);
+        let y = sampler.wrap_v.reduce(uv.y * self.height as f32, self.height);
+        match filter {
+            Filter::Nearest => self.color(sampler, x.floor() as i32, y.floor() as i32),
+            Filter::Linear => {
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i32, y0 as i32);
+                let top = lerp(self.color(sampler, x0, y0), self.color(sampler, x0 + 1, y0), fx);
+                let bottom = lerp(self.color(sampler, x0, y0 + 1), self.color(sampler, x0 + 1, y0 + 1), fx);
+                lerp(top, bottom, fy)
+            }
+        }
+    }
+}
+
+#[inline(always)]
+fn lerp(a: Vec4, b: Vec4, t: f32) -> Vec4 {
+    Vec4::new(
+        a.x + (b.x - a.x) * t,
+        a.y + (b.y - a.y) * t,
+        a.z + (b.z - a.z) * t,
+        a.w + (b.w - a.w) * t
+    )
+}
 
 #[derive(Clone)]
 pub struct Texture {
     pub size: Vec2,
-    pub pixels: Vec<Vec<Vec3>>
+    pub levels: Vec<Buffer>
 }
 impl Texture {
-    pub fn load(path: impl AsRef<Path>) -> Self {
-        let img = image::open(path).unwrap();
-        let mut pixels = vec![vec![Vec3::default();img.width()as usize];img.height()as usize];
-        for (x, y, Rgba::<u8>([r, g, b, _a])) in img.pixels() {
-            pixels[y as usize][x as usize] = Vec3::new(r as f32 / 255., g as f32 / 255., b as f32 / 255.)
+    pub fn load(path: impl AsRef<Path>, color_space: ColorSpace) -> Result<Self> {
+        let path = path.as_ref();
+        let img = image::open(path).map_err(|error| Error::from(error).in_file(path))?;
+        Ok(Self::from_image(img, color_space))
+    }
+    /// Converts an image decoded by `gltf::import`, either embedded (GLB, data uri) or referenced by the file
+    pub fn from_gltf(data: &gltf::image::Data, color_space: ColorSpace) -> Result<Self> {
+        let (w, h) = (data.width, data.height);
+        let bytes = || data.pixels.clone();
+        let shorts = || data.pixels.chunks_exact(2).map(|v| u16::from_le_bytes([v[0], v[1]])).collect::<Vec<_>>();
+        let floats = || data.pixels.chunks_exact(4).map(|v| f32::from_le_bytes([v[0], v[1], v[2], v[3]])).collect::<Vec<_>>();
+        let invalid = || Error::validation(format!("{w}x{h} {:?} image with {} bytes of pixel data", data.format, data.pixels.len()));
+        let img = match data.format {
+            Format::R8 => DynamicImage::ImageLuma8(ImageBuffer::from_raw(w, h, bytes()).ok_or_else(invalid)?),
+            Format::R8G8 => DynamicImage::ImageLumaA8(ImageBuffer::from_raw(w, h, bytes()).ok_or_else(invalid)?),
+            Format::R8G8B8 => DynamicImage::ImageRgb8(ImageBuffer::from_raw(w, h, bytes()).ok_or_else(invalid)?),
+            Format::R8G8B8A8 => DynamicImage::ImageRgba8(ImageBuffer::from_raw(w, h, bytes()).ok_or_else(invalid)?),
+            Format::R16 => DynamicImage::ImageLuma16(ImageBuffer::from_raw(w, h, shorts()).ok_or_else(invalid)?),
+            Format::R16G16 => DynamicImage::ImageLumaA16(ImageBuffer::from_raw(w, h, shorts()).ok_or_else(invalid)?),
+            Format::R16G16B16 => DynamicImage::ImageRgb16(ImageBuffer::from_raw(w, h, shorts()).ok_or_else(invalid)?),
+            Format::R16G16B16A16 => DynamicImage::ImageRgba16(ImageBuffer::from_raw(w, h, shorts()).ok_or_else(invalid)?),
+            Format::R32G32B32FLOAT => DynamicImage::ImageRgb32F(ImageBuffer::from_raw(w, h, floats()).ok_or_else(invalid)?),
+            Format::R32G32B32A32FLOAT => DynamicImage::ImageRgba32F(ImageBuffer::from_raw(w, h, floats()).ok_or_else(invalid)?)
+        };
+        Ok(Self::from_image(img, color_space))
+    }
+    /// Keeps the image channel count (luma, rgb or rgba), 8 and 16 bit images are normalized
+    /// and decoded from `color_space`, float images (.hdr, .exr) are assumed to be linear already
+    pub fn from_image(img: DynamicImage, color_space: ColorSpace) -> Self {
+        let (width, height) = img.dimensions();
+        let channels = match img.color().channel_count() {
+            1 => 1,
+            2 | 4 => 4,
+            _ => 3
+        };
+        let decode = color_space == ColorSpace::Srgb &&
+            !matches!(img.color(), ColorType::Rgb32F | ColorType::Rgba32F);
+        let rgba = img.to_rgba32f();
+        let mut levels = vec![Buffer::from_fn(width as usize, height as usize, channels, |x, y, channel| {
+            let v = rgba.get_pixel(x as u32, y as u32)[channel];
+            if decode && channel < 3 { srgb_to_linear(v) } else { v }
+        })];
+        while levels.last().map_or(false, |level| level.width > 1 || level.height > 1) {
+            let level = levels.last().unwrap().downsample();
+            levels.push(level)
         }
         Self {
-            size: Vec2::new(img.width()as f32, img.height()as f32),
-            pixels
+            size: Vec2::new(width as f32, height as f32),
+            levels
         }
     }
-}
\ No newline at end of file
+    /// Mip level of detail from the screen space derivatives of the uv coordinates
+    #[inline(always)]
+    pub fn lod(&self, duvdx: Vec2, duvdy: Vec2) -> f32 {
+        let dx = Vec2::new(duvdx.x * self.size.x, duvdx.y * self.size.y);
+        let dy = Vec2::new(duvdy.x * self.size.x, duvdy.y * self.size.y);
+        let rho = (dx.x * dx.x + dx.y * dx.y).max(dy.x * dy.x + dy.y * dy.y);
+        0.5 * rho.log2()
+    }
+    #[inline(always)]
+    pub fn sample(&self, sampler: &Sampler, uv: Vec2, lod: f32) -> Vec4 {
+        if lod <= 0. {
+            return self.levels[0].sample(sampler, sampler.mag_filter, uv)
+        }
+        let max_level = (self.levels.len() - 1) as f32;
+        match sampler.mipmap_filter {
+            None => self.levels[0].sample(sampler, sampler.min_filter, uv),
+            Some(Filter::Nearest) =>
+                self.levels[lod.round().min(max_level) as usize].sample(sampler, sampler.min_filter, uv),
+            Some(Filter::Linear) => {
+                let lod = lod.min(max_level);
+                let level = lod.floor();
+                let a = self.levels[level as usize].sample(sampler, sampler.min_filter, uv);
+                let b = self.levels[(level + 1.).min(max_level) as usize].sample(sampler, sampler.min_filter, uv);
+                lerp(a, b, lod - level)
+            }
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use crate::texture::linear_to_srgb;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Operator {
+    /// Clamps to [0, 1]
+    Clamp,
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve
+    Aces
+}
+impl Operator {
+    #[inline(always)]
+    fn apply(self, c: f32) -> f32 {
+        match self {
+            Operator::Clamp => c,
+            Operator::Reinhard => c / (1. + c),
+            Operator::Aces => (c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14)
+        }.max(0.).min(1.)
+    }
+    pub fn next(self) -> Self {
+        match self {
+            Operator::Clamp => Operator::Reinhard,
+            Operator::Reinhard => Operator::Aces,
+            Operator::Aces => Operator::Clamp
+        }
+    }
+}
+
+const BAYER: [f32;16] = [
+     0.,  8.,  2., 10.,
+    12.,  4., 14.,  6.,
+     3., 11.,  1.,  9.,
+    15.,  7., 13.,  5.
+];
+
+/// Maps the linear HDR frame to the 8 bit sRGB output
+pub struct ToneMapper {
+    /// In stops, the color is scaled by `2^exposure` before tone mapping
+    pub exposure: f32,
+    pub operator: Operator,
+    /// Ordered dithering before quantizing, hides banding in smooth gradients
+    pub dither: bool
+}
+impl ToneMapper {
+    /// Passes colors through unchanged, for the debug views
+    pub const RAW: Self = Self {
+        exposure: 0.,
+        operator: Operator::Clamp,
+        dither: false
+    };
+}
+impl Default for ToneMapper {
+    fn default() -> Self {
+        Self {
+            exposure: 0.,
+            operator: Operator::Aces,
+            dither: true
+        }
+    }
+}
+impl ToneMapper {
+    /// `hdr` holds `width` pixels wide linear RGBA floats, `pixels` receives RGBA bytes
+    pub fn encode(&self, width: usize, hdr: &[f32], pixels: &mut [u8]) {
+        let scale = 2f32.powf(self.exposure);
+        for (i, (src, dst)) in hdr.chunks_exact(4).zip(pixels.chunks_exact_mut(4)).enumerate() {
+            let offset = if self.dither {
+                (BAYER[(i / width % 4) * 4 + i % width % 4] + 0.5) / 16.
+            } else {
+                0.5
+            };
+            for c in 0..3 {
+                let v = linear_to_srgb(self.operator.apply(src[c] * scale)) * 255. + offset;
+                dst[c] = v.min(255.) as u8
+            }
+            dst[3] = 255
+        }
+    }
+}